@@ -0,0 +1,203 @@
+use crate::quantile::Quantiles;
+use crate::select_parser::AggregateFunction;
+use crate::sort::compare_values;
+use crate::types::parse_float;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+/// Computes an aggregate function over the values of a group. Empty values
+/// are ignored, except by `count(*)`, and so are values that are not numbers
+/// by `sum`, `avg` and the percentiles.
+#[derive(Debug, Clone)]
+pub enum Accumulator {
+  Count(usize),
+  CountDistinct(HashSet<String>),
+  Sum(Option<f64>),
+  Avg(f64, usize),
+  Min(Option<String>),
+  Max(Option<String>),
+  Percentile(Quantiles, f64),
+}
+
+impl Accumulator {
+  pub fn new(function: AggregateFunction) -> Self {
+    match function {
+      AggregateFunction::Count => Accumulator::Count(0),
+      AggregateFunction::CountDistinct => Accumulator::CountDistinct(HashSet::new()),
+      AggregateFunction::Sum => Accumulator::Sum(None),
+      AggregateFunction::Avg => Accumulator::Avg(0.0, 0),
+      AggregateFunction::Min => Accumulator::Min(None),
+      AggregateFunction::Max => Accumulator::Max(None),
+      AggregateFunction::Median => Accumulator::Percentile(Quantiles::exact(), 0.5),
+      AggregateFunction::Percentile(percentile) => {
+        Accumulator::Percentile(Quantiles::exact(), percentile)
+      }
+      AggregateFunction::ApproxPercentile(percentile) => {
+        Accumulator::Percentile(Quantiles::approximate(), percentile)
+      }
+    }
+  }
+
+  /// Adds a value to the group, `None` standing for the whole row (`count(*)`).
+  pub fn push(&mut self, value: Option<&str>) {
+    let value = match value {
+      None => {
+        if let Accumulator::Count(count) = self {
+          *count += 1;
+        }
+        return;
+      }
+      Some("") => return,
+      Some(value) => value,
+    };
+    match self {
+      Accumulator::Count(count) => *count += 1,
+      Accumulator::CountDistinct(values) => {
+        if !values.contains(value) {
+          values.insert(String::from(value));
+        }
+      }
+      Accumulator::Sum(sum) => {
+        if let Some(number) = parse_float(value) {
+          *sum = Some(sum.unwrap_or(0.0) + number);
+        }
+      }
+      Accumulator::Avg(sum, count) => {
+        if let Some(number) = parse_float(value) {
+          *sum += number;
+          *count += 1;
+        }
+      }
+      Accumulator::Percentile(quantiles, _) => {
+        if let Some(number) = parse_float(value) {
+          quantiles.push(number);
+        }
+      }
+      Accumulator::Min(min) => {
+        if min
+          .as_deref()
+          .is_none_or(|min| compare_values(value, min) == Ordering::Less)
+        {
+          *min = Some(String::from(value));
+        }
+      }
+      Accumulator::Max(max) => {
+        if max
+          .as_deref()
+          .is_none_or(|max| compare_values(value, max) == Ordering::Greater)
+        {
+          *max = Some(String::from(value));
+        }
+      }
+    }
+  }
+
+  /// The result of the function, empty when there was no value to aggregate.
+  pub fn result(&mut self) -> String {
+    match self {
+      Accumulator::Count(count) => count.to_string(),
+      Accumulator::CountDistinct(values) => values.len().to_string(),
+      Accumulator::Sum(sum) => sum.map(format_number).unwrap_or_default(),
+      Accumulator::Avg(_, 0) => String::new(),
+      Accumulator::Avg(sum, count) => format_number(*sum / *count as f64),
+      Accumulator::Min(value) | Accumulator::Max(value) => value.clone().unwrap_or_default(),
+      Accumulator::Percentile(quantiles, percentile) => quantiles
+        .quantile(*percentile)
+        .map(format_number)
+        .unwrap_or_default(),
+    }
+  }
+}
+
+/// Rounds the number to 12 significant digits to hide floating point errors,
+/// like `0.30000000000000004` for `0.1 + 0.2`.
+pub fn format_number(number: f64) -> String {
+  if number.fract() == 0.0 {
+    return number.to_string();
+  }
+  // The scientific notation keeps the given number of significant digits
+  // whatever the magnitude, and the rounded number is displayed without it.
+  let rounded: f64 = format!("{:.11e}", number).parse().unwrap_or(number);
+  match rounded.to_string().as_str() {
+    "-0" => String::from("0"),
+    formatted => String::from(formatted),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn aggregate(function: AggregateFunction, values: &[Option<&str>]) -> String {
+    let mut accumulator = Accumulator::new(function);
+    for value in values {
+      accumulator.push(*value);
+    }
+    accumulator.result()
+  }
+
+  #[test]
+  fn it_counts_values() {
+    let values = [Some("a"), Some(""), Some("b"), Some("a")];
+    assert_eq!("4", aggregate(AggregateFunction::Count, &[None; 4]));
+    assert_eq!("3", aggregate(AggregateFunction::Count, &values));
+    assert_eq!("2", aggregate(AggregateFunction::CountDistinct, &values));
+  }
+
+  #[test]
+  fn it_computes_numeric_aggregates() {
+    let values = [Some("1"), Some(""), Some("2.5"), Some("n/a"), Some("-0.5")];
+    assert_eq!("3", aggregate(AggregateFunction::Sum, &values));
+    assert_eq!("1", aggregate(AggregateFunction::Avg, &values));
+    assert_eq!("", aggregate(AggregateFunction::Avg, &[Some("")]));
+    assert_eq!("", aggregate(AggregateFunction::Sum, &[]));
+    assert_eq!(
+      "0.3",
+      aggregate(AggregateFunction::Sum, &[Some("0.1"), Some("0.2")])
+    );
+  }
+
+  #[test]
+  fn it_formats_numbers_with_12_significant_digits() {
+    assert_eq!("0.3", format_number(0.1 + 0.2));
+    assert_eq!("123456789.123", format_number(123_456_789.123_456_7));
+    assert_eq!(
+      "0.0000000000123456789012",
+      format_number(1.234_567_890_123_4e-11)
+    );
+    assert_eq!("-0.000001", format_number(-0.000_001));
+    assert_eq!("42", format_number(42.0));
+  }
+
+  #[test]
+  fn it_computes_percentiles() {
+    let values = [
+      Some("4"),
+      Some(""),
+      Some("1"),
+      Some("3"),
+      Some("n/a"),
+      Some("2"),
+    ];
+    assert_eq!("2.5", aggregate(AggregateFunction::Median, &values));
+    assert_eq!(
+      "3.7",
+      aggregate(AggregateFunction::Percentile(0.9), &values)
+    );
+    assert_eq!(
+      "3.7",
+      aggregate(AggregateFunction::ApproxPercentile(0.9), &values)
+    );
+    assert_eq!("", aggregate(AggregateFunction::Median, &[Some("")]));
+  }
+
+  #[test]
+  fn it_computes_min_and_max() {
+    let values = [Some("10"), Some("9"), Some(""), Some("-3")];
+    assert_eq!("-3", aggregate(AggregateFunction::Min, &values));
+    assert_eq!("10", aggregate(AggregateFunction::Max, &values));
+    let values = [Some("b"), Some("a"), Some("2")];
+    assert_eq!("2", aggregate(AggregateFunction::Min, &values));
+    assert_eq!("b", aggregate(AggregateFunction::Max, &values));
+  }
+}
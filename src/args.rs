@@ -1,17 +1,30 @@
 use crate::errors::AppError;
+use crate::input::Compression;
+use crate::join::{JoinCondition, JoinType, KeyFilter};
+use crate::melt_parser::Melt;
+use crate::order_parser::OrderBy;
+use crate::output::{OutputFormat, Window};
+use crate::pivot_parser::Pivot;
 use crate::query_parser::Query;
-use crate::select_parser::SelectFields;
+use crate::select_parser::{Expression, SelectFields};
 use clap::Clap;
+use encoding_rs::Encoding;
 
-#[derive(Clap, Debug)]
+pub const DEFAULT_MEMORY_LIMIT: usize = 1 << 30;
+pub const DEFAULT_BINS: usize = 10;
+pub const DEFAULT_MAX_PIVOT_COLUMNS: usize = 100;
+
+#[derive(Clap, Debug, Default)]
 #[clap(
   version = "0.1.0",
   author = "Sébastien Castiel <sebastien@castiel.me>",
   about = "Efficient CSV reader for the console."
 )]
 pub struct Args {
-  #[clap(about = "The CSV file to read.")]
-  pub filename: String,
+  #[clap(
+    about = "The CSV files to read, or glob patterns matching them. Reads from the standard input if omitted or \"-\"."
+  )]
+  pub filenames: Vec<String>,
   #[clap(
     short = 's',
     long = "select",
@@ -27,9 +40,190 @@ pub struct Args {
   #[clap(
     short = 'c',
     long = "count",
+    conflicts_with_all = &["describe", "frequency", "histogram"],
     about = "Counts the number of rows instead of displaying them."
   )]
   pub count: bool,
+  #[clap(
+    long = "join",
+    about = "Another CSV file to join with the rows, before applying the other options. The columns present in both files are renamed a.column and b.column. The order of the rows is not guaranteed without --order-by."
+  )]
+  pub join: Option<String>,
+  #[clap(
+    long = "on",
+    about = "Condition of the join, e.g. \"[Station.Code] = [code]\", with several equalities separated by \"and\"."
+  )]
+  pub on: Option<String>,
+  #[clap(
+    long = "join-type",
+    about = "Type of the join (inner, left, right or full) [default: inner]."
+  )]
+  pub join_type: Option<JoinType>,
+  #[clap(
+    long = "keep-if-in",
+    about = "Keeps only the rows whose value is in a column of another CSV file, e.g. \"allowed.csv:code\" (same column name in both files) or \"[Station.Code]=allowed.csv:code\"."
+  )]
+  pub keep_if_in: Option<String>,
+  #[clap(
+    long = "drop-if-in",
+    about = "Drops the rows whose value is in a column of another CSV file, with the same syntax as --keep-if-in."
+  )]
+  pub drop_if_in: Option<String>,
+  #[clap(
+    long = "melt",
+    about = "Turns the value columns into key and value rows, before applying the other options, e.g. \"id=[Date.Full],[Station.Code] values=Data.Temperature.*\". The values default to all the other columns."
+  )]
+  pub melt: Option<String>,
+  #[clap(
+    long = "pivot",
+    conflicts_with_all = &["select", "group-by", "having", "describe", "frequency", "histogram"],
+    about = "Displays a pivot table, e.g. \"rows=[Station.State] cols=[Date.Month] value=avg([Data.Temperature.Avg Temp])\", with a column for each distinct value of the cols columns. The value defaults to count(*)."
+  )]
+  pub pivot: Option<String>,
+  #[clap(
+    long = "max-pivot-columns",
+    about = "Maximum number of columns generated by --pivot [default: 100]."
+  )]
+  pub max_pivot_columns: Option<usize>,
+  #[clap(
+    long = "describe",
+    conflicts_with_all = &["count", "frequency", "histogram", "group-by", "order-by", "distinct"],
+    about = "Displays statistics about each column (or each selected column) instead of the rows: type, number of empty and non-empty values, distinct values, min, max, mean, standard deviation and examples."
+  )]
+  pub describe: bool,
+  #[clap(
+    long = "frequency",
+    conflicts_with_all = &["count", "describe", "histogram", "select", "group-by", "order-by", "distinct"],
+    about = "Displays the distinct values of a column with their count and percentage, the most frequent first."
+  )]
+  pub frequency: Option<String>,
+  #[clap(
+    long = "histogram",
+    conflicts_with_all = &["count", "describe", "frequency", "select", "group-by", "order-by", "distinct"],
+    about = "Displays the distribution of the values of a numeric column as a histogram, or as buckets with another output format."
+  )]
+  pub histogram: Option<String>,
+  #[clap(
+    long = "bins",
+    about = "Number of buckets of the histogram [default: 10]."
+  )]
+  pub bins: Option<usize>,
+  #[clap(
+    long = "top",
+    about = "With --frequency, displays only the N most frequent values, and groups the other ones in an \"(other)\" row."
+  )]
+  pub top: Option<usize>,
+  #[clap(
+    long = "order-by",
+    about = "Columns to sort the rows on, e.g. \"[Column 1] desc, Col2 asc nulls first\"."
+  )]
+  pub order_by: Option<String>,
+  #[clap(
+    long = "distinct",
+    about = "Removes the duplicate rows, on the selected columns."
+  )]
+  pub distinct: bool,
+  #[clap(
+    long = "group-by",
+    about = "Columns to group the rows on, e.g. \"[Station.State]\", to compute aggregates like count(*), count(distinct Col), sum(Col), avg(Col), min(Col), max(Col), median(Col) or percentile(Col, 0.95) in --select."
+  )]
+  pub group_by: Option<String>,
+  #[clap(
+    long = "having",
+    about = "Filters the groups after aggregation, e.g. \"count(*) > 50\". Can refer to aggregates and to aliases given in --select."
+  )]
+  pub having: Option<String>,
+  #[clap(
+    long = "memory-limit",
+    about = "Memory used to sort the rows before spilling them to temporary files, e.g. \"512M\" or \"2G\" [default: 1G]."
+  )]
+  pub memory_limit: Option<String>,
+  #[clap(
+    long = "temp-dir",
+    about = "Directory for the temporary files used to sort or deduplicate large inputs and to write the sql output [default: the system temporary directory]."
+  )]
+  pub temp_dir: Option<String>,
+  #[clap(
+    short = 'l',
+    long = "limit",
+    about = "Maximum number of rows to display. The reading stops once they are found."
+  )]
+  pub limit: Option<usize>,
+  #[clap(
+    long = "offset",
+    about = "Number of rows to skip before displaying them."
+  )]
+  pub offset: Option<usize>,
+  #[clap(long = "tail", about = "Displays only the last rows.")]
+  pub tail: Option<usize>,
+  #[clap(
+    long = "output",
+    about = "Format of the output: table (default), csv, json, ndjson, markdown, html or sql."
+  )]
+  pub output: Option<OutputFormat>,
+  #[clap(
+    long = "output-delimiter",
+    about = "Delimiter used for the csv output [default: ,]."
+  )]
+  pub output_delimiter: Option<char>,
+  #[clap(
+    short = 'o',
+    long = "output-file",
+    about = "Writes the output to the given file instead of the standard output. The format is chosen from its extension (.csv, .json, .ndjson, .md, .html, .sql) unless --output is given."
+  )]
+  pub output_file: Option<String>,
+  #[clap(
+    long = "typed",
+    about = "Writes numbers, booleans and nulls in JSON outputs, according to the column types inferred from the first rows."
+  )]
+  pub typed: bool,
+  #[clap(long = "table-name", about = "Name of the table in the sql output.")]
+  pub table_name: Option<String>,
+  #[clap(
+    long = "batch-size",
+    about = "Number of rows per INSERT statement in the sql output [default: 100]."
+  )]
+  pub batch_size: Option<usize>,
+  #[clap(
+    long = "compression",
+    about = "Compression of the input (none, gzip, zstd, bzip2 or xz). Detected automatically if omitted."
+  )]
+  pub compression: Option<Compression>,
+  #[clap(
+    long = "encoding",
+    about = "Encoding of the input, e.g. \"latin1\", \"windows-1252\" or \"utf-16le\". Detected from the BOM if omitted, falling back to Windows-1252 for input that is not valid UTF-8."
+  )]
+  pub encoding: Option<String>,
+  #[clap(
+    long = "union-by-name",
+    about = "Aligns the columns of several files by name instead of requiring identical headers."
+  )]
+  pub union_by_name: bool,
+  #[clap(
+    long = "file-column",
+    about = "Adds a virtual _file column containing the file each row comes from."
+  )]
+  pub file_column: bool,
+  #[clap(
+    long = "flexible",
+    about = "Accepts rows with a different number of cells than the headers, padding missing cells with empty values and dropping extra ones."
+  )]
+  pub flexible: bool,
+  #[clap(
+    long = "skip-bad-rows",
+    about = "Skips the rows that cannot be parsed instead of aborting, and reports them at the end."
+  )]
+  pub skip_bad_rows: bool,
+  #[clap(
+    long = "exact-widths",
+    about = "Reads all the rows before displaying the table, to compute the exact column widths."
+  )]
+  pub exact_widths: bool,
+  #[clap(
+    long = "sample-rows",
+    about = "Number of rows read to compute the column widths or infer the column types of JSON outputs before streaming them [default: 1000]."
+  )]
+  pub sample_rows: Option<usize>,
 }
 
 impl Args {
@@ -43,6 +237,173 @@ impl Args {
     }
   }
 
+  pub fn parse_encoding(&self) -> Result<Option<&'static Encoding>, AppError> {
+    match &self.encoding {
+      Some(label) => match Encoding::for_label(label.as_bytes()) {
+        Some(encoding) => Ok(Some(encoding)),
+        None => Err(AppError::InvalidEncoding(label.clone())),
+      },
+      None => Ok(None),
+    }
+  }
+
+  pub fn window(&self) -> Window {
+    Window {
+      offset: self.offset.unwrap_or(0),
+      limit: self.limit,
+      tail: self.tail,
+    }
+  }
+
+  pub fn output_format(&self) -> OutputFormat {
+    self
+      .output
+      .or_else(|| {
+        self
+          .output_file
+          .as_deref()
+          .and_then(OutputFormat::from_extension)
+      })
+      .unwrap_or(OutputFormat::Table)
+  }
+
+  pub fn parse_output_delimiter(&self) -> Result<u8, AppError> {
+    match self.output_delimiter {
+      Some(delimiter) if delimiter.is_ascii() => Ok(delimiter as u8),
+      Some(delimiter) => Err(AppError::InvalidDelimiter(delimiter)),
+      None => Ok(b','),
+    }
+  }
+
+  pub fn parse_order_by(&self) -> Result<Option<OrderBy>, AppError> {
+    match &self.order_by {
+      Some(order_by) => match OrderBy::parse(order_by) {
+        Ok(order_by) => Ok(Some(order_by)),
+        Err(_) => Err(AppError::OrderByParsingError(order_by.clone())),
+      },
+      None => Ok(None),
+    }
+  }
+
+  pub fn parse_memory_limit(&self) -> Result<usize, AppError> {
+    let memory_limit = match &self.memory_limit {
+      Some(memory_limit) => memory_limit.trim(),
+      None => return Ok(DEFAULT_MEMORY_LIMIT),
+    };
+    let (number, unit) = match memory_limit.find(|c: char| !c.is_ascii_digit()) {
+      Some(position) => memory_limit.split_at(position),
+      None => (memory_limit, ""),
+    };
+    let multiplier: usize = match unit.trim().to_ascii_uppercase().as_str() {
+      "" | "B" => 1,
+      "K" | "KB" => 1 << 10,
+      "M" | "MB" => 1 << 20,
+      "G" | "GB" => 1 << 30,
+      _ => return Err(AppError::InvalidMemoryLimit(memory_limit.to_string())),
+    };
+    number
+      .parse::<usize>()
+      .ok()
+      .and_then(|number| number.checked_mul(multiplier))
+      .ok_or_else(|| AppError::InvalidMemoryLimit(memory_limit.to_string()))
+  }
+
+  pub fn parse_having(&self) -> Result<Option<Query>, AppError> {
+    match &self.having {
+      Some(having) => match Query::parse(having) {
+        Ok(having) => Ok(Some(having)),
+        Err(_) => Err(AppError::HavingParsingError(having.clone())),
+      },
+      None => Ok(None),
+    }
+  }
+
+  pub fn parse_frequency(&self) -> Result<Option<String>, AppError> {
+    match &self.frequency {
+      Some(frequency) => match parse_column(frequency) {
+        Some(column) => Ok(Some(column)),
+        None => Err(AppError::FrequencyParsingError(frequency.clone())),
+      },
+      None => Ok(None),
+    }
+  }
+
+  pub fn parse_histogram(&self) -> Result<Option<String>, AppError> {
+    match &self.histogram {
+      Some(histogram) => match parse_column(histogram) {
+        Some(column) => Ok(Some(column)),
+        None => Err(AppError::HistogramParsingError(histogram.clone())),
+      },
+      None => Ok(None),
+    }
+  }
+
+  pub fn parse_bins(&self) -> Result<usize, AppError> {
+    match self.bins {
+      Some(0) => Err(AppError::InvalidBins),
+      Some(bins) => Ok(bins),
+      None => Ok(DEFAULT_BINS),
+    }
+  }
+
+  pub fn parse_melt(&self) -> Result<Option<Melt>, AppError> {
+    match &self.melt {
+      Some(melt) => match Melt::parse(melt) {
+        Ok(melt) => Ok(Some(melt)),
+        Err(_) => Err(AppError::MeltParsingError(melt.clone())),
+      },
+      None => Ok(None),
+    }
+  }
+
+  pub fn parse_on(&self) -> Result<Option<JoinCondition>, AppError> {
+    match (&self.join, &self.on) {
+      (Some(_), Some(on)) => match JoinCondition::parse(on) {
+        Ok(condition) => Ok(Some(condition)),
+        Err(_) => Err(AppError::JoinParsingError(on.clone())),
+      },
+      (Some(_), None) => Err(AppError::MissingJoinCondition),
+      (None, _) => Ok(None),
+    }
+  }
+
+  pub fn parse_keep_if_in(&self) -> Result<Option<KeyFilter>, AppError> {
+    self.keep_if_in.as_deref().map(parse_key_filter).transpose()
+  }
+
+  pub fn parse_drop_if_in(&self) -> Result<Option<KeyFilter>, AppError> {
+    self.drop_if_in.as_deref().map(parse_key_filter).transpose()
+  }
+
+  pub fn parse_pivot(&self) -> Result<Option<Pivot>, AppError> {
+    match &self.pivot {
+      Some(pivot) => match Pivot::parse(pivot) {
+        Ok(pivot) => Ok(Some(pivot)),
+        Err(_) => Err(AppError::PivotParsingError(pivot.clone())),
+      },
+      None => Ok(None),
+    }
+  }
+
+  pub fn parse_group_by(&self) -> Result<Option<Vec<String>>, AppError> {
+    let group_by = match &self.group_by {
+      Some(group_by) => group_by,
+      None => return Ok(None),
+    };
+    let fields = match SelectFields::parse(group_by) {
+      Ok(fields) => fields.0,
+      Err(_) => return Err(AppError::GroupByParsingError(group_by.clone())),
+    };
+    let mut columns = Vec::with_capacity(fields.len());
+    for field in fields {
+      match (field.expression, field.alias) {
+        (Expression::Column(column), None) => columns.push(column),
+        _ => return Err(AppError::GroupByParsingError(group_by.clone())),
+      }
+    }
+    Ok(Some(columns))
+  }
+
   pub fn parse_select(&self) -> Result<Option<SelectFields>, AppError> {
     match &self.select {
       Some(select) => match SelectFields::parse(select) {
@@ -53,3 +414,39 @@ impl Args {
     }
   }
 }
+
+/// Parses `file:column` or `column=file:column`.
+fn parse_key_filter(filter: &str) -> Result<KeyFilter, AppError> {
+  let error = || AppError::KeyFilterParsingError(String::from(filter));
+  let (column, other) = match filter.split_once('=') {
+    Some((column, other)) => (Some(column), other),
+    None => (None, filter),
+  };
+  let (filename, other_column) = other.rsplit_once(':').ok_or_else(error)?;
+  let other_column = parse_column(other_column).ok_or_else(error)?;
+  let column = match column {
+    Some(column) => parse_column(column).ok_or_else(error)?,
+    None => other_column.clone(),
+  };
+  match filename.trim() {
+    "" => Err(error()),
+    filename => Ok(KeyFilter {
+      column,
+      filename: String::from(filename),
+      other_column,
+    }),
+  }
+}
+
+/// Parses a single column name, with or without brackets.
+fn parse_column(column: &str) -> Option<String> {
+  match SelectFields::parse(column) {
+    Ok(SelectFields(fields)) if fields.len() == 1 && fields[0].alias.is_none() => {
+      match &fields[0].expression {
+        Expression::Column(column) => Some(column.clone()),
+        Expression::Aggregate(_) => None,
+      }
+    }
+    _ => None,
+  }
+}
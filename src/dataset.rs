@@ -0,0 +1,393 @@
+use crate::errors::AppError;
+use crate::input::Source;
+use crate::melt_parser::Melt;
+use crate::Args;
+use csv::StringRecord;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+pub const FILE_COLUMN: &str = "_file";
+pub const MELT_KEY_COLUMN: &str = "key";
+pub const MELT_VALUE_COLUMN: &str = "value";
+const BAD_ROWS_SAMPLE_SIZE: usize = 5;
+
+pub type Records = Box<dyn Iterator<Item = Result<StringRecord, Box<dyn Error>>>>;
+pub type SharedBadRows = Rc<RefCell<BadRows>>;
+
+pub struct Dataset {
+  pub headers: Vec<String>,
+  pub records: Records,
+  pub bad_rows: SharedBadRows,
+}
+
+#[derive(Debug, Default)]
+pub struct BadRows {
+  pub count: usize,
+  samples: Vec<String>,
+}
+
+impl BadRows {
+  fn add(&mut self, filename: &str, err: &csv::Error) {
+    self.count += 1;
+    if self.samples.len() < BAD_ROWS_SAMPLE_SIZE {
+      self.samples.push(format!("{}: {}", filename, err));
+    }
+  }
+}
+
+impl Display for BadRows {
+  fn fmt(&self, formatter: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+    writeln!(
+      formatter,
+      "Skipped {} bad {}:",
+      self.count,
+      if self.count > 1 { "rows" } else { "row" }
+    )?;
+    for sample in &self.samples {
+      writeln!(formatter, "  {}", sample)?;
+    }
+    if self.count > self.samples.len() {
+      writeln!(
+        formatter,
+        "  ... and {} more",
+        self.count - self.samples.len()
+      )?;
+    }
+    Ok(())
+  }
+}
+
+impl Dataset {
+  /// Reads all the sources as a single table. Their headers must be identical,
+  /// unless `--union-by-name` is given, in which case the columns are aligned
+  /// by name and missing cells are left empty.
+  ///
+  /// With `--flexible`, rows are padded with empty cells or truncated to match
+  /// the headers. With `--skip-bad-rows`, rows that cannot be parsed are
+  /// skipped and recorded in `bad_rows` instead of aborting.
+  pub fn concat(sources: Vec<Source>, args: &Args) -> Result<Self, Box<dyn Error>> {
+    let flexible = args.flexible;
+    let csv_reader = move |input| {
+      csv::ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(flexible)
+        .from_reader(input)
+    };
+    // The headers of all the files are read first, one file at a time. The files
+    // are then opened again when their rows are reached, except the ones that
+    // can only be read once.
+    let mut readers = Vec::new();
+    for mut source in sources {
+      let mut reader = csv_reader(source.open()?);
+      let file_headers: Vec<String> = reader.headers()?.iter().map(String::from).collect();
+      let reader = if source.reopenable {
+        None
+      } else {
+        Some(reader)
+      };
+      readers.push((source, file_headers, reader));
+    }
+
+    let mut headers: Vec<String> = Vec::new();
+    for (i, (source, file_headers, _)) in readers.iter().enumerate() {
+      if args.union_by_name {
+        for header in file_headers {
+          if !headers.contains(header) {
+            headers.push(header.clone());
+          }
+        }
+      } else if i == 0 {
+        headers = file_headers.clone();
+      } else if &headers != file_headers {
+        return Err(Box::new(AppError::HeadersMismatch(source.name.clone())));
+      }
+    }
+
+    let file_column = args.file_column;
+    let skip_bad_rows = args.skip_bad_rows;
+    let bad_rows = Rc::new(RefCell::new(BadRows::default()));
+    let all_bad_rows = bad_rows.clone();
+    let all_headers = headers.clone();
+    let records =
+      readers
+        .into_iter()
+        .flat_map(move |(mut source, file_headers, reader)| -> Records {
+          let reader = match reader {
+            Some(reader) => reader,
+            None => match source.open() {
+              Ok(input) => csv_reader(input),
+              Err(err) => return Box::new(std::iter::once(Err(err))),
+            },
+          };
+          let name = source.name;
+          let positions: Vec<Option<usize>> = all_headers
+            .iter()
+            .map(|header| file_headers.iter().position(|h| h == header))
+            .collect();
+          let aligned = file_column || file_headers != all_headers;
+          let bad_rows = all_bad_rows.clone();
+          Box::new(reader.into_records().filter_map(move |record| {
+            let record = match record {
+              Ok(record) => record,
+              Err(err) if skip_bad_rows && !matches!(err.kind(), csv::ErrorKind::Io(_)) => {
+                bad_rows.borrow_mut().add(&name, &err);
+                return None;
+              }
+              Err(err) => return Some(Err(Box::new(err) as Box<dyn Error>)),
+            };
+            if !aligned && record.len() == positions.len() {
+              return Some(Ok(record));
+            }
+            let mut aligned_record =
+              StringRecord::with_capacity(record.as_slice().len(), positions.len() + 1);
+            for position in &positions {
+              aligned_record.push_field(position.and_then(|pos| record.get(pos)).unwrap_or(""));
+            }
+            if file_column {
+              aligned_record.push_field(&name);
+            }
+            Some(Ok(aligned_record))
+          }))
+        });
+
+    if file_column {
+      headers.push(String::from(FILE_COLUMN));
+    }
+    Ok(Dataset {
+      headers,
+      records: Box::new(records),
+      bad_rows,
+    })
+  }
+
+  /// Turns each row into one row per value column, with the id columns, the
+  /// name of the value column in a `key` column, and its value in a `value`
+  /// column.
+  pub fn melt(self, melt: &Melt) -> Result<Self, AppError> {
+    let mut id_positions = Vec::with_capacity(melt.ids.len());
+    for id in &melt.ids {
+      match self.headers.iter().position(|header| header == id) {
+        Some(position) => id_positions.push(position),
+        None => return Err(AppError::InvalidFieldInMelt(id.clone())),
+      }
+    }
+    let mut values: Vec<(usize, String)> = Vec::new();
+    match &melt.values {
+      Some(patterns) => {
+        for pattern in patterns {
+          let matcher = glob::Pattern::new(pattern).ok();
+          let mut matched = false;
+          for (position, header) in self.headers.iter().enumerate() {
+            let matches = match &matcher {
+              Some(matcher) => matcher.matches(header),
+              None => header == pattern,
+            };
+            if matches && !id_positions.contains(&position) {
+              matched = true;
+              if !values.iter().any(|(value, _)| *value == position) {
+                values.push((position, header.clone()));
+              }
+            }
+          }
+          if !matched {
+            return Err(AppError::InvalidFieldInMelt(pattern.clone()));
+          }
+        }
+      }
+      None => {
+        for (position, header) in self.headers.iter().enumerate() {
+          if !id_positions.contains(&position) {
+            values.push((position, header.clone()));
+          }
+        }
+      }
+    }
+
+    let mut headers = melt.ids.clone();
+    headers.push(String::from(MELT_KEY_COLUMN));
+    headers.push(String::from(MELT_VALUE_COLUMN));
+    let records = self.records.flat_map(move |record| {
+      let rows: Vec<Result<StringRecord, Box<dyn Error>>> = match record {
+        Ok(record) => values
+          .iter()
+          .map(|(position, header)| {
+            let mut row = StringRecord::with_capacity(0, id_positions.len() + 2);
+            for &id_position in &id_positions {
+              row.push_field(record.get(id_position).unwrap_or(""));
+            }
+            row.push_field(header);
+            row.push_field(record.get(*position).unwrap_or(""));
+            Ok(row)
+          })
+          .collect(),
+        Err(err) => vec![Err(err)],
+      };
+      rows
+    });
+    Ok(Dataset {
+      headers,
+      records: Box::new(records),
+      bad_rows: self.bad_rows,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn source(name: &str, content: &'static str) -> Source {
+    Source::from_reader(name, Box::new(content.as_bytes()))
+  }
+
+  fn collect(dataset: Dataset) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
+    let mut rows = vec![dataset.headers];
+    for record in dataset.records {
+      rows.push(record?.iter().map(String::from).collect());
+    }
+    Ok(rows)
+  }
+
+  #[test]
+  fn it_concatenates_sources_with_same_headers() -> Result<(), Box<dyn Error>> {
+    let dataset = Dataset::concat(
+      vec![source("a.csv", "x,y\n1,2\n"), source("b.csv", "x,y\n3,4\n")],
+      &Args::default(),
+    )?;
+    assert_eq!(
+      vec![vec!["x", "y"], vec!["1", "2"], vec!["3", "4"]],
+      collect(dataset)?
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn it_opens_files_again_to_read_their_rows() -> Result<(), Box<dyn Error>> {
+    let mut paths = Vec::new();
+    let mut sources = Vec::new();
+    for (name, content) in &[("a.csv", "x,y\n1,2\n"), ("b.csv", "y,z\n3,4\n")] {
+      let path = std::env::temp_dir().join(format!("csvread-{}-{}", std::process::id(), name));
+      std::fs::write(&path, content)?;
+      sources.push(Source::file(path.to_str().unwrap(), None, None));
+      paths.push(path);
+    }
+    let args = Args {
+      union_by_name: true,
+      ..Default::default()
+    };
+    let rows = collect(Dataset::concat(sources, &args)?);
+    for path in paths {
+      std::fs::remove_file(path)?;
+    }
+    assert_eq!(
+      vec![vec!["x", "y", "z"], vec!["1", "2", ""], vec!["", "3", "4"]],
+      rows?
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn it_rejects_sources_with_different_headers() {
+    let dataset = Dataset::concat(
+      vec![source("a.csv", "x,y\n1,2\n"), source("b.csv", "y,x\n3,4\n")],
+      &Args::default(),
+    );
+    assert!(dataset.is_err());
+  }
+
+  #[test]
+  fn it_aligns_sources_by_name() -> Result<(), Box<dyn Error>> {
+    let args = Args {
+      union_by_name: true,
+      file_column: true,
+      ..Default::default()
+    };
+    let dataset = Dataset::concat(
+      vec![source("a.csv", "x,y\n1,2\n"), source("b.csv", "z,x\n3,4\n")],
+      &args,
+    )?;
+    assert_eq!(
+      vec![
+        vec!["x", "y", "z", "_file"],
+        vec!["1", "2", "", "a.csv"],
+        vec!["4", "", "3", "b.csv"]
+      ],
+      collect(dataset)?
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn it_pads_and_truncates_rows_when_flexible() -> Result<(), Box<dyn Error>> {
+    let args = Args {
+      flexible: true,
+      ..Default::default()
+    };
+    let dataset = Dataset::concat(vec![source("a.csv", "x,y\n1\n2,3,4\n")], &args)?;
+    assert_eq!(
+      vec![vec!["x", "y"], vec!["1", ""], vec!["2", "3"]],
+      collect(dataset)?
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn it_fails_on_ragged_rows() {
+    let dataset = Dataset::concat(vec![source("a.csv", "x,y\n1\n")], &Args::default());
+    assert!(collect(dataset.unwrap()).is_err());
+  }
+
+  #[test]
+  fn it_skips_bad_rows() -> Result<(), Box<dyn Error>> {
+    let args = Args {
+      skip_bad_rows: true,
+      ..Default::default()
+    };
+    let dataset = Dataset::concat(vec![source("a.csv", "x,y\n1\n2,3\n4,5,6\n")], &args)?;
+    let bad_rows = dataset.bad_rows.clone();
+    assert_eq!(vec![vec!["x", "y"], vec!["2", "3"]], collect(dataset)?);
+    assert_eq!(2, bad_rows.borrow().count);
+    assert!(bad_rows
+      .borrow()
+      .to_string()
+      .starts_with("Skipped 2 bad rows:\n  a.csv: CSV error: record 1 (line: 2, byte: 4)"));
+    Ok(())
+  }
+
+  #[test]
+  fn it_melts_columns() -> Result<(), Box<dyn Error>> {
+    let dataset = Dataset::concat(
+      vec![source("a.csv", "id,t.min,t.max,other\n1,2,3,x\n4,5,6,y\n")],
+      &Args::default(),
+    )?;
+    let melt = Melt {
+      ids: vec![String::from("id")],
+      values: Some(vec![String::from("t.*")]),
+    };
+    assert_eq!(
+      vec![
+        vec!["id", "key", "value"],
+        vec!["1", "t.min", "2"],
+        vec!["1", "t.max", "3"],
+        vec!["4", "t.min", "5"],
+        vec!["4", "t.max", "6"]
+      ],
+      collect(dataset.melt(&melt)?)?
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn it_rejects_melting_unknown_columns() -> Result<(), Box<dyn Error>> {
+    let dataset = || Dataset::concat(vec![source("a.csv", "x,y\n1,2\n")], &Args::default());
+    let melt = |ids: &str, values: &str| Melt {
+      ids: vec![String::from(ids)],
+      values: Some(vec![String::from(values)]),
+    };
+    assert!(dataset()?.melt(&melt("z", "y")).is_err());
+    assert!(dataset()?.melt(&melt("x", "z*")).is_err());
+    Ok(())
+  }
+}
@@ -0,0 +1,234 @@
+use crate::aggregate::format_number;
+use crate::sort::compare_values;
+use crate::types::{parse_float, ColumnType};
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+pub const DESCRIBE_HEADERS: [&str; 10] = [
+  "column", "type", "non_null", "null", "distinct", "min", "max", "mean", "stddev", "examples",
+];
+const EXAMPLES: usize = 3;
+const EXACT_DISTINCT_LIMIT: usize = 10_000;
+const HYPERLOGLOG_BITS: u32 = 12;
+
+/// Statistics about the values of a column, computed in a single pass.
+pub struct ColumnStats {
+  name: String,
+  column_type: ColumnType,
+  non_null: usize,
+  null: usize,
+  distinct: DistinctCount,
+  min: Option<String>,
+  max: Option<String>,
+  // Welford's online algorithm for the mean and the variance.
+  numbers: usize,
+  mean: f64,
+  squares: f64,
+  examples: Vec<String>,
+}
+
+impl ColumnStats {
+  pub fn new(name: &str) -> Self {
+    ColumnStats {
+      name: String::from(name),
+      column_type: ColumnType::Null,
+      non_null: 0,
+      null: 0,
+      distinct: DistinctCount::Exact(HashSet::new()),
+      min: None,
+      max: None,
+      numbers: 0,
+      mean: 0.0,
+      squares: 0.0,
+      examples: Vec::new(),
+    }
+  }
+
+  pub fn push(&mut self, value: &str) {
+    if value.is_empty() {
+      self.null += 1;
+      return;
+    }
+    self.non_null += 1;
+    self.column_type = self.column_type.merge(ColumnType::of(value));
+    if self.distinct.insert(value) && self.examples.len() < EXAMPLES {
+      self.examples.push(String::from(value));
+    }
+    if self
+      .min
+      .as_deref()
+      .is_none_or(|min| compare_values(value, min) == Ordering::Less)
+    {
+      self.min = Some(String::from(value));
+    }
+    if self
+      .max
+      .as_deref()
+      .is_none_or(|max| compare_values(value, max) == Ordering::Greater)
+    {
+      self.max = Some(String::from(value));
+    }
+    if let Some(number) = parse_float(value) {
+      self.numbers += 1;
+      let delta = number - self.mean;
+      self.mean += delta / self.numbers as f64;
+      self.squares += delta * (number - self.mean);
+    }
+  }
+
+  /// The statistics, in the order of `DESCRIBE_HEADERS`. The mean and standard
+  /// deviation are only given for numeric columns, and approximate distinct
+  /// counts are prefixed with `~`.
+  pub fn row(&self) -> Vec<String> {
+    let is_numeric = matches!(self.column_type, ColumnType::Integer | ColumnType::Float);
+    let (mean, stddev) = match (is_numeric, self.numbers) {
+      (true, 1) => (format_number(self.mean), String::new()),
+      (true, numbers) => (
+        format_number(self.mean),
+        format_number((self.squares / (numbers - 1) as f64).sqrt()),
+      ),
+      (false, _) => (String::new(), String::new()),
+    };
+    vec![
+      self.name.clone(),
+      String::from(self.column_type.name()),
+      self.non_null.to_string(),
+      self.null.to_string(),
+      self.distinct.to_string(),
+      self.min.clone().unwrap_or_default(),
+      self.max.clone().unwrap_or_default(),
+      mean,
+      stddev,
+      self.examples.join(" | "),
+    ]
+  }
+}
+
+/// Counts the distinct values exactly, until there are too many of them to
+/// keep in memory. Then they are estimated with HyperLogLog, with a typical
+/// error of 1.6%.
+enum DistinctCount {
+  Exact(HashSet<String>),
+  Approximate(Vec<u8>),
+}
+
+impl DistinctCount {
+  /// Adds the value, returning whether it was not seen before (always false
+  /// once the count is approximate).
+  fn insert(&mut self, value: &str) -> bool {
+    match self {
+      DistinctCount::Exact(values) => {
+        if values.contains(value) {
+          return false;
+        }
+        values.insert(String::from(value));
+        if values.len() > EXACT_DISTINCT_LIMIT {
+          let mut registers = vec![0; 1 << HYPERLOGLOG_BITS];
+          for value in values.iter() {
+            add_to_registers(&mut registers, value);
+          }
+          *self = DistinctCount::Approximate(registers);
+        }
+        true
+      }
+      DistinctCount::Approximate(registers) => {
+        add_to_registers(registers, value);
+        false
+      }
+    }
+  }
+}
+
+impl std::fmt::Display for DistinctCount {
+  fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+    match self {
+      DistinctCount::Exact(values) => formatter.write_fmt(format_args!("{}", values.len())),
+      DistinctCount::Approximate(registers) => {
+        formatter.write_fmt(format_args!("~{}", estimate_cardinality(registers)))
+      }
+    }
+  }
+}
+
+fn add_to_registers(registers: &mut [u8], value: &str) {
+  let mut hasher = DefaultHasher::new();
+  value.hash(&mut hasher);
+  let hash = hasher.finish();
+  let index = (hash >> (64 - HYPERLOGLOG_BITS)) as usize;
+  let rank = ((hash << HYPERLOGLOG_BITS) | (1 << (HYPERLOGLOG_BITS - 1))).leading_zeros() as u8 + 1;
+  registers[index] = std::cmp::max(registers[index], rank);
+}
+
+fn estimate_cardinality(registers: &[u8]) -> usize {
+  let m = registers.len() as f64;
+  let alpha = 0.7213 / (1.0 + 1.079 / m);
+  let sum: f64 = registers
+    .iter()
+    .map(|&rank| 2f64.powi(-(rank as i32)))
+    .sum();
+  let estimate = alpha * m * m / sum;
+  let zeros = registers.iter().filter(|&&rank| rank == 0).count();
+  if estimate <= 2.5 * m && zeros > 0 {
+    (m * (m / zeros as f64).ln()).round() as usize
+  } else {
+    estimate.round() as usize
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn describe(values: &[&str]) -> Vec<String> {
+    let mut stats = ColumnStats::new("col");
+    for value in values {
+      stats.push(value);
+    }
+    stats.row()
+  }
+
+  #[test]
+  fn it_describes_numeric_columns() {
+    assert_eq!(
+      vec![
+        "col",
+        "float",
+        "5",
+        "1",
+        "4",
+        "-1",
+        "10",
+        "3.3",
+        "4.08656334834",
+        "2 | 10 | -1"
+      ],
+      describe(&["2", "10", "", "-1", "2", "3.5"])
+    );
+  }
+
+  #[test]
+  fn it_describes_text_columns() {
+    assert_eq!(
+      vec!["col", "string", "3", "0", "2", "1", "b", "", "", "b | 1"],
+      describe(&["b", "1", "b"])
+    );
+    assert_eq!(
+      vec!["col", "null", "0", "2", "0", "", "", "", "", ""],
+      describe(&["", ""])
+    );
+  }
+
+  #[test]
+  fn it_estimates_high_distinct_counts() {
+    let mut stats = ColumnStats::new("col");
+    for i in 0..100_000 {
+      stats.push(&i.to_string());
+    }
+    let distinct = stats.row()[4].clone();
+    assert!(distinct.starts_with('~'));
+    let distinct: f64 = distinct[1..].parse().unwrap();
+    assert!((distinct - 100_000.0).abs() < 5_000.0);
+  }
+}
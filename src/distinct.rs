@@ -0,0 +1,155 @@
+use crate::sort::{SortedRows, ALLOCATION_OVERHEAD};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter, Seek, SeekFrom};
+use std::path::PathBuf;
+
+const PARTITIONS: usize = 16;
+
+/// Removes the duplicate rows, keeping the first occurrence of each one.
+///
+/// The rows already seen are kept in memory, so that the distinct rows can be
+/// output right away. Once they exceed the memory limit, the new rows are
+/// split by hash into temporary partition files instead, each of them being
+/// deduplicated separately at the end. The rows from the partitions come last,
+/// so the order of the rows is not kept in this case.
+pub struct Distinct {
+  seen: HashSet<Vec<String>>,
+  memory: usize,
+  memory_limit: usize,
+  temp_dir: PathBuf,
+  partitions: Vec<csv::Writer<BufWriter<File>>>,
+}
+
+impl Distinct {
+  pub fn new(memory_limit: usize, temp_dir: Option<PathBuf>) -> Self {
+    Distinct {
+      seen: HashSet::new(),
+      memory: 0,
+      memory_limit,
+      temp_dir: temp_dir.unwrap_or_else(std::env::temp_dir),
+      partitions: Vec::new(),
+    }
+  }
+
+  /// Returns the row if it can be output now, that is if it was not seen
+  /// before and the rows have not been spilled to disk.
+  pub fn push(&mut self, row: Vec<String>) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+    if self.seen.contains(&row) {
+      return Ok(None);
+    }
+    if !self.partitions.is_empty() {
+      let mut hasher = DefaultHasher::new();
+      row.hash(&mut hasher);
+      let partition = hasher.finish() as usize % PARTITIONS;
+      self.partitions[partition].write_record(&row)?;
+      return Ok(None);
+    }
+    self.memory += row
+      .iter()
+      .map(|cell| cell.len() + ALLOCATION_OVERHEAD)
+      .sum::<usize>()
+      + 2 * ALLOCATION_OVERHEAD;
+    self.seen.insert(row.clone());
+    if self.memory > self.memory_limit {
+      for _ in 0..PARTITIONS {
+        let file = tempfile::tempfile_in(&self.temp_dir)?;
+        self.partitions.push(
+          csv::WriterBuilder::new()
+            .flexible(true)
+            .from_writer(BufWriter::new(file)),
+        );
+      }
+    }
+    Ok(Some(row))
+  }
+
+  /// The distinct rows that were spilled to disk.
+  pub fn finish(self) -> Result<SortedRows, Box<dyn Error>> {
+    let mut files = Vec::with_capacity(self.partitions.len());
+    for partition in self.partitions {
+      let mut file = partition.into_inner()?.into_inner()?;
+      file.seek(SeekFrom::Start(0))?;
+      files.push(file);
+    }
+    let rows = files.into_iter().flat_map(|file| {
+      let mut seen = HashSet::new();
+      csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(BufReader::new(file))
+        .into_records()
+        .filter_map(move |record| match record {
+          Ok(record) => {
+            let row: Vec<String> = record.iter().map(String::from).collect();
+            if seen.contains(&row) {
+              None
+            } else {
+              seen.insert(row.clone());
+              Some(Ok(row))
+            }
+          }
+          Err(err) => Some(Err(Box::new(err) as Box<dyn Error>)),
+        })
+    });
+    Ok(Box::new(rows))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn distinct(memory_limit: usize, rows: &[&[&str]]) -> Vec<Vec<String>> {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut distinct = Distinct::new(memory_limit, Some(temp_dir.path().into()));
+    let mut result = Vec::new();
+    for row in rows {
+      let row = row.iter().map(|cell| String::from(*cell)).collect();
+      result.extend(distinct.push(row).unwrap());
+    }
+    for row in distinct.finish().unwrap() {
+      result.push(row.unwrap());
+    }
+    assert_eq!(0, std::fs::read_dir(temp_dir.path()).unwrap().count());
+    result
+  }
+
+  #[test]
+  fn it_keeps_the_first_occurrence_of_rows() {
+    let rows: &[&[&str]] = &[&["a", "1"], &["b", "2"], &["a", "1"], &["a", "2"]];
+    assert_eq!(
+      vec![vec!["a", "1"], vec!["b", "2"], vec!["a", "2"]],
+      distinct(1 << 20, rows)
+    );
+  }
+
+  #[test]
+  fn it_spills_rows_to_disk() {
+    let rows: &[&[&str]] = &[
+      &["a", "1"],
+      &["b", "2"],
+      &["a", "1"],
+      &["c", "3"],
+      &["c", "3"],
+      &["b", "2"],
+      &["d", ""],
+      &["d", ""],
+    ];
+    let mut result = distinct(200, rows);
+    assert_eq!(vec![vec!["a", "1"], vec!["b", "2"]], result[..2].to_vec());
+    result.sort();
+    assert_eq!(
+      vec![
+        vec!["a", "1"],
+        vec!["b", "2"],
+        vec!["c", "3"],
+        vec!["d", ""],
+      ],
+      result
+    );
+  }
+}
@@ -7,6 +7,36 @@ pub enum AppError {
   WhereParsingError(String),
   InvalidFieldInWhereClause(String),
   InvalidFieldInSelectClause(String),
+  OrderByParsingError(String),
+  InvalidFieldInOrderByClause(String),
+  InvalidCompression(String),
+  NoFileMatchingPattern(String),
+  HeadersMismatch(String),
+  InvalidEncoding(String),
+  InvalidOutputFormat(String),
+  InvalidDelimiter(char),
+  MissingTableName,
+  InvalidMemoryLimit(String),
+  GroupByParsingError(String),
+  InvalidFieldInGroupByClause(String),
+  FieldNotGrouped(String),
+  HavingParsingError(String),
+  InvalidFieldInHavingClause(String),
+  FrequencyParsingError(String),
+  InvalidFieldInFrequency(String),
+  HistogramParsingError(String),
+  InvalidFieldInHistogram(String),
+  InvalidBins,
+  PivotParsingError(String),
+  TooManyPivotColumns(usize),
+  MeltParsingError(String),
+  InvalidFieldInMelt(String),
+  InvalidJoinType(String),
+  JoinParsingError(String),
+  InvalidFieldInJoin(String),
+  MissingJoinCondition,
+  KeyFilterParsingError(String),
+  InvalidFieldInKeyFilter(String),
 }
 
 impl Display for AppError {
@@ -24,6 +54,108 @@ impl Display for AppError {
       AppError::InvalidFieldInWhereClause(field) => {
         formatter.write_fmt(format_args!("Invalid field in where clause: {}.", field))
       }
+      AppError::OrderByParsingError(clause) => {
+        formatter.write_fmt(format_args!("Error parsing the order by clause: {}", clause))
+      }
+      AppError::InvalidFieldInOrderByClause(field) => {
+        formatter.write_fmt(format_args!("Invalid field in order by clause: {}.", field))
+      }
+      AppError::GroupByParsingError(clause) => {
+        formatter.write_fmt(format_args!("Error parsing the group by clause: {}", clause))
+      }
+      AppError::InvalidFieldInGroupByClause(field) => {
+        formatter.write_fmt(format_args!("Invalid field in group by clause: {}.", field))
+      }
+      AppError::HavingParsingError(clause) => {
+        formatter.write_fmt(format_args!("Error parsing the having clause: {}", clause))
+      }
+      AppError::InvalidFieldInHavingClause(field) => {
+        formatter.write_fmt(format_args!("Invalid field in having clause: {}.", field))
+      }
+      AppError::FrequencyParsingError(column) => formatter.write_fmt(format_args!(
+        "Error parsing the frequency column: {} (expected a single column).",
+        column
+      )),
+      AppError::InvalidFieldInFrequency(field) => {
+        formatter.write_fmt(format_args!("Invalid field for frequency: {}.", field))
+      }
+      AppError::HistogramParsingError(column) => formatter.write_fmt(format_args!(
+        "Error parsing the histogram column: {} (expected a single column).",
+        column
+      )),
+      AppError::InvalidFieldInHistogram(field) => {
+        formatter.write_fmt(format_args!("Invalid field for histogram: {}.", field))
+      }
+      AppError::InvalidBins => formatter.write_str("The number of bins must be at least 1."),
+      AppError::PivotParsingError(pivot) => formatter.write_fmt(format_args!(
+        "Error parsing the pivot: {} (expected rows=... cols=... value=...).",
+        pivot
+      )),
+      AppError::TooManyPivotColumns(max) => formatter.write_fmt(format_args!(
+        "The pivot table would have more than {} columns (use --max-pivot-columns to allow more).",
+        max
+      )),
+      AppError::MeltParsingError(melt) => formatter.write_fmt(format_args!(
+        "Error parsing the melt: {} (expected id=... values=...).",
+        melt
+      )),
+      AppError::InvalidFieldInMelt(field) => {
+        formatter.write_fmt(format_args!("Invalid field in melt: {}.", field))
+      }
+      AppError::InvalidJoinType(join_type) => formatter.write_fmt(format_args!(
+        "Invalid join type: {} (expected inner, left, right or full).",
+        join_type
+      )),
+      AppError::JoinParsingError(condition) => formatter.write_fmt(format_args!(
+        "Error parsing the join condition: {} (expected [column] = [other column]).",
+        condition
+      )),
+      AppError::InvalidFieldInJoin(condition) => {
+        formatter.write_fmt(format_args!("Invalid field in join condition: {}.", condition))
+      }
+      AppError::MissingJoinCondition => {
+        formatter.write_str("--join requires a join condition (use --on).")
+      }
+      AppError::KeyFilterParsingError(filter) => formatter.write_fmt(format_args!(
+        "Error parsing the filter: {} (expected file:column or column=file:column).",
+        filter
+      )),
+      AppError::InvalidFieldInKeyFilter(field) => {
+        formatter.write_fmt(format_args!("Invalid field in filter: {}.", field))
+      }
+      AppError::FieldNotGrouped(field) => formatter.write_fmt(format_args!(
+        "The field {} must be in the group by clause or used in an aggregate function.",
+        field
+      )),
+      AppError::InvalidCompression(compression) => formatter.write_fmt(format_args!(
+        "Invalid compression: {} (expected none, gzip, zstd, bzip2 or xz).",
+        compression
+      )),
+      AppError::NoFileMatchingPattern(pattern) => {
+        formatter.write_fmt(format_args!("No file matching pattern: {}.", pattern))
+      }
+      AppError::HeadersMismatch(filename) => formatter.write_fmt(format_args!(
+        "The headers of {} do not match the ones of the first file (use --union-by-name to align them).",
+        filename
+      )),
+      AppError::InvalidEncoding(encoding) => {
+        formatter.write_fmt(format_args!("Invalid encoding: {}.", encoding))
+      }
+      AppError::InvalidOutputFormat(format) => formatter.write_fmt(format_args!(
+        "Invalid output format: {} (expected table, csv, json, ndjson, markdown, html or sql).",
+        format
+      )),
+      AppError::InvalidMemoryLimit(memory_limit) => formatter.write_fmt(format_args!(
+        "Invalid memory limit: {} (expected a size like 512M or 2G).",
+        memory_limit
+      )),
+      AppError::MissingTableName => {
+        formatter.write_str("The sql output requires a table name (use --table-name).")
+      }
+      AppError::InvalidDelimiter(delimiter) => formatter.write_fmt(format_args!(
+        "Invalid delimiter: {} (expected an ASCII character).",
+        delimiter
+      )),
     }
   }
 }
@@ -0,0 +1,155 @@
+use crate::aggregate::format_number;
+use std::io::Write;
+
+pub const BUCKET_HEADERS: [&str; 3] = ["from", "to", "count"];
+const BAR_WIDTH: usize = 50;
+const BAR: &str = "#";
+
+#[derive(Debug, PartialEq)]
+pub struct Bucket {
+  pub from: f64,
+  pub to: f64,
+  pub count: usize,
+}
+
+impl Bucket {
+  pub fn row(&self) -> Vec<String> {
+    vec![
+      format_number(self.from),
+      format_number(self.to),
+      self.count.to_string(),
+    ]
+  }
+}
+
+/// Splits the range of the values into `bins` buckets of the same width. Each
+/// bucket includes its lower bound, and the last one its upper bound too.
+pub fn buckets(values: &[f64], bins: usize) -> Vec<Bucket> {
+  let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+  let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+  if values.is_empty() {
+    return Vec::new();
+  }
+  if min == max {
+    return vec![Bucket {
+      from: min,
+      to: max,
+      count: values.len(),
+    }];
+  }
+  let width = (max - min) / bins as f64;
+  let mut counts = vec![0; bins];
+  for value in values {
+    let index = ((value - min) / width) as usize;
+    counts[std::cmp::min(index, bins - 1)] += 1;
+  }
+  counts
+    .into_iter()
+    .enumerate()
+    .map(|(i, count)| Bucket {
+      from: min + i as f64 * width,
+      to: if i == bins - 1 {
+        max
+      } else {
+        min + (i + 1) as f64 * width
+      },
+      count,
+    })
+    .collect()
+}
+
+/// Writes the buckets as an ASCII bar chart, the longest bar being
+/// `BAR_WIDTH` characters long.
+pub fn write_chart(buckets: &[Bucket], writer: &mut dyn Write) -> std::io::Result<()> {
+  let labels: Vec<String> = buckets
+    .iter()
+    .enumerate()
+    .map(|(i, bucket)| {
+      format!(
+        "[{}, {}{}",
+        format_number(bucket.from),
+        format_number(bucket.to),
+        if i == buckets.len() - 1 { "]" } else { ")" }
+      )
+    })
+    .collect();
+  let label_width = labels.iter().map(|label| label.len()).max().unwrap_or(0);
+  let count_width = buckets
+    .iter()
+    .map(|bucket| bucket.count.to_string().len())
+    .max()
+    .unwrap_or(0);
+  let max_count = buckets.iter().map(|bucket| bucket.count).max().unwrap_or(0);
+  for (label, bucket) in labels.iter().zip(buckets) {
+    // Rounded up, so that non-empty buckets are always visible.
+    let bar = (bucket.count * BAR_WIDTH).div_ceil(max_count);
+    let line = format!(
+      "{:<label_width$} {:>count_width$} {}",
+      label,
+      bucket.count,
+      BAR.repeat(bar),
+      label_width = label_width,
+      count_width = count_width
+    );
+    writeln!(writer, "{}", line.trim_end())?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_splits_values_into_buckets() {
+    assert_eq!(
+      vec![
+        Bucket {
+          from: 0.0,
+          to: 2.5,
+          count: 2
+        },
+        Bucket {
+          from: 2.5,
+          to: 5.0,
+          count: 0
+        },
+        Bucket {
+          from: 5.0,
+          to: 7.5,
+          count: 1
+        },
+        Bucket {
+          from: 7.5,
+          to: 10.0,
+          count: 2
+        },
+      ],
+      buckets(&[0.0, 10.0, 2.0, 7.5, 5.0], 4)
+    );
+    assert_eq!(
+      vec![Bucket {
+        from: 1.0,
+        to: 1.0,
+        count: 2
+      }],
+      buckets(&[1.0, 1.0], 4)
+    );
+    assert_eq!(Vec::<Bucket>::new(), buckets(&[], 4));
+  }
+
+  #[test]
+  fn it_writes_a_chart() {
+    let mut out = Vec::new();
+    write_chart(&buckets(&[0.0, 10.0, 2.0, 7.5, 5.0, 0.5, 1.0], 4), &mut out).unwrap();
+    assert_eq!(
+      format!(
+        "[0, 2.5)  4 {}\n[2.5, 5)  0\n[5, 7.5)  1 {}\n[7.5, 10] 2 {}\n",
+        BAR.repeat(50),
+        BAR.repeat(13),
+        BAR.repeat(25)
+      ),
+      String::from_utf8(out).unwrap()
+    );
+  }
+}
@@ -0,0 +1,455 @@
+use crate::errors::AppError;
+use encoding_rs::{Encoding, WINDOWS_1252};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+const STDIN_FILENAME: &str = "-";
+const DECODE_CHUNK_SIZE: usize = 8 * 1024;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Compression {
+  None,
+  Gzip,
+  Zstd,
+  Bzip2,
+  Xz,
+}
+
+impl Compression {
+  fn from_extension(filename: &str) -> Option<Self> {
+    match Path::new(filename).extension()?.to_str()? {
+      "gz" => Some(Compression::Gzip),
+      "zst" => Some(Compression::Zstd),
+      "bz2" => Some(Compression::Bzip2),
+      "xz" => Some(Compression::Xz),
+      _ => None,
+    }
+  }
+
+  fn from_magic_bytes(bytes: &[u8]) -> Self {
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+      Compression::Gzip
+    } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+      Compression::Zstd
+    } else if bytes.len() >= 4 && bytes.starts_with(b"BZh") && (b'1'..=b'9').contains(&bytes[3]) {
+      Compression::Bzip2
+    } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+      Compression::Xz
+    } else {
+      Compression::None
+    }
+  }
+}
+
+impl FromStr for Compression {
+  type Err = AppError;
+
+  fn from_str(compression: &str) -> Result<Self, Self::Err> {
+    match compression {
+      "none" => Ok(Compression::None),
+      "gzip" | "gz" => Ok(Compression::Gzip),
+      "zstd" | "zst" => Ok(Compression::Zstd),
+      "bzip2" | "bz2" => Ok(Compression::Bzip2),
+      "xz" => Ok(Compression::Xz),
+      _ => Err(AppError::InvalidCompression(String::from(compression))),
+    }
+  }
+}
+
+type Opener = Box<dyn FnMut() -> Result<Box<dyn Read>, Box<dyn Error>>>;
+
+/// An input that is only opened when it is read, so that many files don't have
+/// to be open at the same time. Files can be opened several times, e.g. once to
+/// read their headers and once to read their rows, unlike the standard input.
+pub struct Source {
+  pub name: String,
+  pub reopenable: bool,
+  open: Opener,
+}
+
+impl Source {
+  /// The given file, or the standard input if it is `-`. See `open` for the
+  /// decompression and decoding.
+  pub fn file(
+    filename: &str,
+    compression: Option<Compression>,
+    encoding: Option<&'static Encoding>,
+  ) -> Self {
+    let name = String::from(filename);
+    let path = name.clone();
+    Source {
+      reopenable: name != STDIN_FILENAME,
+      name,
+      open: Box::new(move || open(&path, compression, encoding)),
+    }
+  }
+
+  /// An input that is already open, and can therefore be read only once.
+  #[cfg(test)]
+  pub fn from_reader(name: &str, input: Box<dyn Read>) -> Self {
+    let mut input = Some(input);
+    let name = String::from(name);
+    let error_name = name.clone();
+    Source {
+      name,
+      reopenable: false,
+      open: Box::new(move || match input.take() {
+        Some(input) => Ok(input),
+        None => Err(Box::new(std::io::Error::other(format!(
+          "{} can only be read once",
+          error_name
+        )))),
+      }),
+    }
+  }
+
+  pub fn open(&mut self) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    (self.open)()
+  }
+}
+
+/// The given files, expanding glob patterns, or the standard input if there is
+/// none. The files are not opened yet.
+pub fn sources(
+  filenames: &[String],
+  compression: Option<Compression>,
+  encoding: Option<&'static Encoding>,
+) -> Result<Vec<Source>, Box<dyn Error>> {
+  if filenames.is_empty() {
+    return Ok(vec![Source::file(STDIN_FILENAME, compression, encoding)]);
+  }
+  Ok(
+    expand_globs(filenames)?
+      .iter()
+      .map(|filename| Source::file(filename, compression, encoding))
+      .collect(),
+  )
+}
+
+/// Opens the given file (or the standard input if it is `-`), decompressing it
+/// on the fly. When no compression is forced, it is detected from the file
+/// extension, then from the first bytes of the stream. The content is then
+/// transcoded to UTF-8 (see `decode`).
+pub fn open(
+  filename: &str,
+  compression: Option<Compression>,
+  encoding: Option<&'static Encoding>,
+) -> Result<Box<dyn Read>, Box<dyn Error>> {
+  let input = match filename {
+    STDIN_FILENAME => decompress(std::io::stdin(), compression)?,
+    filename => decompress(
+      File::open(filename)?,
+      compression.or_else(|| Compression::from_extension(filename)),
+    )?,
+  };
+  decode(input, encoding)
+}
+
+/// Total size in bytes of the given files, expanding glob patterns, or `None`
+/// when reading from the standard input.
+pub fn total_size(filenames: &[String]) -> Option<u64> {
+  if filenames.is_empty() {
+    return None;
+  }
+  let mut size = 0;
+  for filename in expand_globs(filenames).ok()? {
+    if filename == STDIN_FILENAME {
+      return None;
+    }
+    size += std::fs::metadata(filename).ok()?.len();
+  }
+  Some(size)
+}
+
+/// Files that exist are used as is, even if their name contains `*`, `?` or `[`.
+fn expand_globs(filenames: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+  let mut expanded = Vec::new();
+  for filename in filenames {
+    if !filename.contains(&['*', '?', '['][..]) || Path::new(filename).exists() {
+      expanded.push(filename.clone());
+      continue;
+    }
+    let mut matches = Vec::new();
+    for path in glob::glob(filename)? {
+      matches.push(path?.to_string_lossy().into_owned());
+    }
+    if matches.is_empty() {
+      return Err(Box::new(AppError::NoFileMatchingPattern(filename.clone())));
+    }
+    expanded.append(&mut matches);
+  }
+  Ok(expanded)
+}
+
+fn decompress(
+  input: impl Read + 'static,
+  compression: Option<Compression>,
+) -> Result<Box<dyn Read>, Box<dyn Error>> {
+  let mut input = BufReader::new(input);
+  let compression = match compression {
+    Some(compression) => compression,
+    None => Compression::from_magic_bytes(input.fill_buf()?),
+  };
+  Ok(match compression {
+    Compression::None => Box::new(input),
+    Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(input)),
+    Compression::Zstd => Box::new(zstd::Decoder::with_buffer(input)?),
+    Compression::Bzip2 => Box::new(bzip2::bufread::MultiBzDecoder::new(input)),
+    Compression::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(input)),
+  })
+}
+
+/// Transcodes the input to UTF-8, stripping the BOM if there is one. A BOM
+/// always takes precedence over the given encoding. Without BOM nor given
+/// encoding, the input is read as UTF-8 until its first invalid sequence, and
+/// as Windows-1252 (a superset of Latin-1) from there.
+fn decode(
+  input: impl Read + 'static,
+  encoding: Option<&'static Encoding>,
+) -> Result<Box<dyn Read>, Box<dyn Error>> {
+  let mut input = BufReader::new(input);
+  if encoding.is_none() && Encoding::for_bom(input.fill_buf()?).is_none() {
+    return Ok(Box::new(Utf8OrWindows1252::new(input)));
+  }
+  Ok(Box::new(
+    DecodeReaderBytesBuilder::new()
+      .encoding(encoding)
+      .utf8_passthru(true)
+      .strip_bom(true)
+      .bom_override(true)
+      .build(input),
+  ))
+}
+
+/// Passes UTF-8 input through as is, checking it while it is read, and
+/// switches to Windows-1252 at the first invalid sequence.
+struct Utf8OrWindows1252<R> {
+  input: R,
+  decoder: Option<encoding_rs::Decoder>,
+  pending: Vec<u8>,
+  output: Vec<u8>,
+  position: usize,
+  eof: bool,
+}
+
+impl<R: Read> Utf8OrWindows1252<R> {
+  fn new(input: R) -> Self {
+    Utf8OrWindows1252 {
+      input,
+      decoder: None,
+      pending: Vec::new(),
+      output: Vec::new(),
+      position: 0,
+      eof: false,
+    }
+  }
+
+  fn fill_output(&mut self) -> io::Result<()> {
+    let mut chunk = [0; DECODE_CHUNK_SIZE];
+    let read = self.input.read(&mut chunk)?;
+    self.eof = read == 0;
+    self.pending.extend_from_slice(&chunk[..read]);
+    self.output.clear();
+    self.position = 0;
+    if self.decoder.is_none() {
+      match std::str::from_utf8(&self.pending) {
+        Ok(_) => {
+          self.output.append(&mut self.pending);
+          return Ok(());
+        }
+        // A character split between two chunks.
+        Err(err) if err.error_len().is_none() && !self.eof => {
+          self.output.extend(self.pending.drain(..err.valid_up_to()));
+          return Ok(());
+        }
+        Err(err) => {
+          self.output.extend(self.pending.drain(..err.valid_up_to()));
+          self.decoder = Some(WINDOWS_1252.new_decoder_without_bom_handling());
+        }
+      }
+    }
+    if let Some(decoder) = &mut self.decoder {
+      let start = self.output.len();
+      let length = decoder
+        .max_utf8_buffer_length(self.pending.len())
+        .unwrap_or(self.pending.len() * 3);
+      self.output.resize(start + length, 0);
+      let (_, _, written, _) =
+        decoder.decode_to_utf8(&self.pending, &mut self.output[start..], self.eof);
+      self.output.truncate(start + written);
+      self.pending.clear();
+    }
+    Ok(())
+  }
+}
+
+impl<R: Read> Read for Utf8OrWindows1252<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    while self.position == self.output.len() {
+      if self.eof {
+        return Ok(0);
+      }
+      self.fill_output()?;
+    }
+    let length = buf.len().min(self.output.len() - self.position);
+    buf[..length].copy_from_slice(&self.output[self.position..self.position + length]);
+    self.position += length;
+    Ok(length)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::{Cursor, Write};
+
+  const CSV: &str = "city,country\nParis,France\nLondon,UK\n";
+
+  fn read_all(compressed: Vec<u8>, compression: Option<Compression>) -> String {
+    let mut out = String::new();
+    decompress(Cursor::new(compressed), compression)
+      .unwrap()
+      .read_to_string(&mut out)
+      .unwrap();
+    out
+  }
+
+  #[test]
+  fn it_reads_uncompressed_input() {
+    assert_eq!(CSV, read_all(CSV.as_bytes().to_vec(), None));
+  }
+
+  #[test]
+  fn it_detects_gzip() {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(CSV.as_bytes()).unwrap();
+    assert_eq!(CSV, read_all(encoder.finish().unwrap(), None));
+  }
+
+  #[test]
+  fn it_detects_zstd() {
+    let compressed = zstd::encode_all(CSV.as_bytes(), 0).unwrap();
+    assert_eq!(CSV, read_all(compressed, None));
+  }
+
+  #[test]
+  fn it_detects_bzip2() {
+    let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+    encoder.write_all(CSV.as_bytes()).unwrap();
+    assert_eq!(CSV, read_all(encoder.finish().unwrap(), None));
+  }
+
+  #[test]
+  fn it_reads_headers_looking_like_bzip2_as_uncompressed() {
+    let csv = "BZhx,BZh\n1,2\n";
+    assert_eq!(csv, read_all(csv.as_bytes().to_vec(), None));
+  }
+
+  #[test]
+  fn it_detects_xz() {
+    let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+    encoder.write_all(CSV.as_bytes()).unwrap();
+    assert_eq!(CSV, read_all(encoder.finish().unwrap(), None));
+  }
+
+  #[test]
+  fn it_uses_forced_compression() {
+    assert_eq!(
+      CSV,
+      read_all(CSV.as_bytes().to_vec(), Some(Compression::None))
+    );
+  }
+
+  fn decode_all(bytes: &'static [u8], encoding: Option<&'static Encoding>) -> String {
+    let mut out = String::new();
+    decode(bytes, encoding)
+      .unwrap()
+      .read_to_string(&mut out)
+      .unwrap();
+    out
+  }
+
+  #[test]
+  fn it_strips_utf8_bom() {
+    assert_eq!("Data,Date\n", decode_all(b"\xEF\xBB\xBFData,Date\n", None));
+  }
+
+  #[test]
+  fn it_detects_utf16_from_bom() {
+    assert_eq!(
+      "a,\u{e9}\n",
+      decode_all(b"\xFF\xFEa\x00,\x00\xE9\x00\n\x00", None)
+    );
+  }
+
+  #[test]
+  fn it_falls_back_to_windows_1252() {
+    assert_eq!("caf\u{e9},\u{20ac}\n", decode_all(b"caf\xE9,\x80\n", None));
+  }
+
+  #[test]
+  fn it_falls_back_to_windows_1252_after_the_first_chunk() {
+    let mut bytes = "a,\u{e9}\n".repeat(DECODE_CHUNK_SIZE).into_bytes();
+    bytes.extend_from_slice(b"caf\xE9,\x80\n");
+    let mut out = String::new();
+    decode(Cursor::new(bytes), None)
+      .unwrap()
+      .read_to_string(&mut out)
+      .unwrap();
+    assert_eq!(
+      format!(
+        "{}caf\u{e9},\u{20ac}\n",
+        "a,\u{e9}\n".repeat(DECODE_CHUNK_SIZE)
+      ),
+      out
+    );
+  }
+
+  #[test]
+  fn it_uses_given_encoding() {
+    assert_eq!(
+      "\u{e9}t\u{e9}\n",
+      decode_all(b"\xE9t\xE9\n", Encoding::for_label(b"latin1"))
+    );
+  }
+
+  #[test]
+  fn it_expands_glob_patterns() -> Result<(), Box<dyn Error>> {
+    assert_eq!(
+      vec![
+        String::from("Cargo.toml"),
+        String::from("example_data/weather.csv")
+      ],
+      expand_globs(&[
+        String::from("Cargo.toml"),
+        String::from("example_data/*.csv")
+      ])?
+    );
+    assert!(expand_globs(&[String::from("example_data/*.json")]).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn it_uses_existing_files_with_glob_characters_as_is() -> Result<(), Box<dyn Error>> {
+    let path = std::env::temp_dir().join(format!("csvread-{}-data[1].csv", std::process::id()));
+    std::fs::write(&path, CSV)?;
+    let filenames = vec![String::from(path.to_str().unwrap())];
+    let expanded = expand_globs(&filenames);
+    std::fs::remove_file(&path)?;
+    assert_eq!(filenames, expanded?);
+    Ok(())
+  }
+
+  #[test]
+  fn it_detects_compression_from_extension() {
+    assert_eq!(
+      Some(Compression::Zstd),
+      Compression::from_extension("logs/2020-01-01.csv.zst")
+    );
+    assert_eq!(None, Compression::from_extension("weather.csv"));
+  }
+}
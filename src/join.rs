@@ -0,0 +1,472 @@
+use crate::dataset::Dataset;
+use crate::errors::AppError;
+use csv::StringRecord;
+use nom::branch::alt;
+use nom::bytes::complete::{tag, tag_no_case, take_while1};
+use nom::character::complete::{multispace0, multispace1};
+use nom::combinator::all_consuming;
+use nom::multi::separated_list1;
+use nom::sequence::tuple;
+use nom::IResult;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::rc::Rc;
+use std::str::FromStr;
+
+pub type ParsingError<'a> = nom::Err<nom::error::Error<&'a str>>;
+
+const LEFT_PREFIX: &str = "a.";
+const RIGHT_PREFIX: &str = "b.";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum JoinType {
+  Inner,
+  Left,
+  Right,
+  Full,
+}
+
+impl JoinType {
+  fn keeps_left(self) -> bool {
+    matches!(self, JoinType::Left | JoinType::Full)
+  }
+
+  fn keeps_right(self) -> bool {
+    matches!(self, JoinType::Right | JoinType::Full)
+  }
+}
+
+impl FromStr for JoinType {
+  type Err = AppError;
+
+  fn from_str(join_type: &str) -> Result<Self, Self::Err> {
+    match join_type {
+      "inner" => Ok(JoinType::Inner),
+      "left" => Ok(JoinType::Left),
+      "right" => Ok(JoinType::Right),
+      "full" | "outer" => Ok(JoinType::Full),
+      _ => Err(AppError::InvalidJoinType(String::from(join_type))),
+    }
+  }
+}
+
+/// Pairs of columns that must be equal, e.g. `[Station.Code] = code and ...`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct JoinCondition(pub Vec<(String, String)>);
+
+impl JoinCondition {
+  pub fn parse(condition: &str) -> Result<Self, ParsingError<'_>> {
+    match all_consuming(Self::parse_condition)(condition) {
+      Ok((_, condition)) => Ok(condition),
+      Err(err) => Err(err),
+    }
+  }
+
+  fn parse_condition(input: &str) -> IResult<&str, Self> {
+    let (input, pairs) = separated_list1(
+      tuple((tag_no_case("and"), multispace1)),
+      Self::parse_equality,
+    )(input)?;
+    Ok((input, JoinCondition(pairs)))
+  }
+
+  fn parse_equality(input: &str) -> IResult<&str, (String, String)> {
+    let (input, (left, _, right)) = tuple((Self::parse_field, tag("="), Self::parse_field))(input)?;
+    Ok((input, (String::from(left), String::from(right))))
+  }
+
+  fn parse_field(input: &str) -> IResult<&str, &str> {
+    alt((
+      Self::parse_field_with_brackets,
+      Self::parse_field_without_brackets,
+    ))(input)
+  }
+
+  fn parse_field_without_brackets(input: &str) -> IResult<&str, &str> {
+    let (input, (_, field, _)) = tuple((
+      multispace0,
+      take_while1(|c| c != ' ' && c != '='),
+      multispace0,
+    ))(input)?;
+    Ok((input, field))
+  }
+
+  fn parse_field_with_brackets(input: &str) -> IResult<&str, &str> {
+    let (input, (_, _, field, _, _)) = tuple((
+      multispace0,
+      tag("["),
+      take_while1(|c| c != ']'),
+      tag("]"),
+      multispace0,
+    ))(input)?;
+    Ok((input, field))
+  }
+}
+
+/// Keeps (or drops) the rows whose value of `column` is among the values of
+/// `other_column` in the file `filename`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct KeyFilter {
+  pub column: String,
+  pub filename: String,
+  pub other_column: String,
+}
+
+/// Joins the rows of `left` (`a`) and `right` (`b`) having equal values for
+/// the condition columns, with a hash join: the rows of one side are loaded in
+/// memory, and the rows of the other side are streamed. Empty values never
+/// match. The rows follow the order of the streamed side, then come the
+/// unmatched rows of the other side, so the order depends on `build_left`.
+///
+/// The columns are the ones of `left` followed by the ones of `right`. Those
+/// whose name is in both are prefixed with `a.` and `b.`. The result keeps the
+/// bad rows of `left`: the caller must report the ones of `right` itself.
+pub fn join(
+  left: Dataset,
+  right: Dataset,
+  condition: &JoinCondition,
+  join_type: JoinType,
+  build_left: bool,
+) -> Result<Dataset, AppError> {
+  let mut left_positions = Vec::with_capacity(condition.0.len());
+  let mut right_positions = Vec::with_capacity(condition.0.len());
+  for (a, b) in &condition.0 {
+    let positions = match (
+      position(&left.headers, a, LEFT_PREFIX),
+      position(&right.headers, b, RIGHT_PREFIX),
+    ) {
+      (Some(left_position), Some(right_position)) => Some((left_position, right_position)),
+      _ => match (
+        position(&left.headers, b, LEFT_PREFIX),
+        position(&right.headers, a, RIGHT_PREFIX),
+      ) {
+        (Some(left_position), Some(right_position)) => Some((left_position, right_position)),
+        _ => None,
+      },
+    };
+    match positions {
+      Some((left_position, right_position)) => {
+        left_positions.push(left_position);
+        right_positions.push(right_position);
+      }
+      None => return Err(AppError::InvalidFieldInJoin(format!("{} = {}", a, b))),
+    }
+  }
+
+  let mut headers = Vec::with_capacity(left.headers.len() + right.headers.len());
+  for header in &left.headers {
+    match right.headers.contains(header) {
+      true => headers.push(format!("{}{}", LEFT_PREFIX, header)),
+      false => headers.push(header.clone()),
+    }
+  }
+  for header in &right.headers {
+    match left.headers.contains(header) {
+      true => headers.push(format!("{}{}", RIGHT_PREFIX, header)),
+      false => headers.push(header.clone()),
+    }
+  }
+
+  let widths = (left.headers.len(), right.headers.len());
+  let bad_rows = left.bad_rows.clone();
+  let records = if build_left {
+    hash_join(
+      left,
+      left_positions,
+      join_type.keeps_left(),
+      right,
+      right_positions,
+      join_type.keeps_right(),
+      widths,
+      true,
+    )
+  } else {
+    hash_join(
+      right,
+      right_positions,
+      join_type.keeps_right(),
+      left,
+      left_positions,
+      join_type.keeps_left(),
+      widths,
+      false,
+    )
+  };
+  Ok(Dataset {
+    headers,
+    records,
+    bad_rows,
+  })
+}
+
+/// Keeps the rows of `dataset` whose value of `filter.column` is in `other`
+/// (or is not, when `keep` is false). The values of `other` are loaded in a
+/// hash set before the rows are read. Empty values are never in `other`. As
+/// with `join`, the caller must report the bad rows of `other`.
+pub fn semi_join(
+  dataset: Dataset,
+  other: Dataset,
+  filter: &KeyFilter,
+  keep: bool,
+) -> Result<Dataset, Box<dyn Error>> {
+  let position = match dataset
+    .headers
+    .iter()
+    .position(|header| *header == filter.column)
+  {
+    Some(position) => position,
+    None => {
+      return Err(Box::new(AppError::InvalidFieldInKeyFilter(
+        filter.column.clone(),
+      )))
+    }
+  };
+  let other_position = match other
+    .headers
+    .iter()
+    .position(|header| *header == filter.other_column)
+  {
+    Some(position) => position,
+    None => {
+      return Err(Box::new(AppError::InvalidFieldInKeyFilter(
+        filter.other_column.clone(),
+      )))
+    }
+  };
+  let mut values = HashSet::new();
+  for record in other.records {
+    match record?.get(other_position) {
+      Some("") | None => {}
+      Some(value) => {
+        values.insert(String::from(value));
+      }
+    }
+  }
+  let records = dataset.records.filter(move |record| match record {
+    Ok(record) => values.contains(record.get(position).unwrap_or("")) == keep,
+    Err(_) => true,
+  });
+  Ok(Dataset {
+    headers: dataset.headers,
+    records: Box::new(records),
+    bad_rows: dataset.bad_rows,
+  })
+}
+
+fn position(headers: &[String], field: &str, prefix: &str) -> Option<usize> {
+  headers
+    .iter()
+    .position(|header| header == field)
+    .or_else(|| {
+      let field = field.strip_prefix(prefix)?;
+      headers.iter().position(|header| header == field)
+    })
+}
+
+fn key(record: &StringRecord, positions: &[usize]) -> Option<Vec<String>> {
+  let mut key = Vec::with_capacity(positions.len());
+  for &position in positions {
+    match record.get(position).unwrap_or("") {
+      "" => return None,
+      value => key.push(String::from(value)),
+    }
+  }
+  Some(key)
+}
+
+/// Streams the `probe` rows, matching them with the `build` rows loaded in
+/// memory. The unmatched build rows are output at the end if kept.
+#[allow(clippy::too_many_arguments)]
+fn hash_join(
+  build: Dataset,
+  build_positions: Vec<usize>,
+  keep_build: bool,
+  probe: Dataset,
+  probe_positions: Vec<usize>,
+  keep_probe: bool,
+  (left_width, right_width): (usize, usize),
+  build_is_left: bool,
+) -> Box<dyn Iterator<Item = Result<StringRecord, Box<dyn Error>>>> {
+  let combine = move |build: Option<&StringRecord>, probe: Option<&StringRecord>| {
+    let (left, right) = if build_is_left {
+      (build, probe)
+    } else {
+      (probe, build)
+    };
+    let mut row = StringRecord::with_capacity(0, left_width + right_width);
+    for i in 0..left_width {
+      row.push_field(left.and_then(|left| left.get(i)).unwrap_or(""));
+    }
+    for i in 0..right_width {
+      row.push_field(right.and_then(|right| right.get(i)).unwrap_or(""));
+    }
+    row
+  };
+
+  let mut build_rows = Vec::new();
+  let mut index: HashMap<Vec<String>, Vec<usize>> = HashMap::new();
+  for record in build.records {
+    let record = match record {
+      Ok(record) => record,
+      Err(err) => return Box::new(std::iter::once(Err(err))),
+    };
+    if let Some(key) = key(&record, &build_positions) {
+      index.entry(key).or_default().push(build_rows.len());
+    }
+    build_rows.push(record);
+  }
+  let build_rows = Rc::new(build_rows);
+  let matched = Rc::new(RefCell::new(vec![false; build_rows.len()]));
+
+  let probe_rows = build_rows.clone();
+  let probe_matched = matched.clone();
+  let matches = probe.records.flat_map(move |record| {
+    let record = match record {
+      Ok(record) => record,
+      Err(err) => return vec![Err(err)],
+    };
+    let indices = key(&record, &probe_positions).and_then(|key| index.get(&key));
+    match indices {
+      Some(indices) => indices
+        .iter()
+        .map(|&i| {
+          probe_matched.borrow_mut()[i] = true;
+          Ok(combine(Some(&probe_rows[i]), Some(&record)))
+        })
+        .collect(),
+      None if keep_probe => vec![Ok(combine(None, Some(&record)))],
+      None => Vec::new(),
+    }
+  });
+  // Evaluated lazily, once all the probe rows have been matched.
+  let unmatched = std::iter::once(()).flat_map(move |_| {
+    let matched = matched.borrow();
+    let rows: Vec<Result<StringRecord, Box<dyn Error>>> = match keep_build {
+      true => build_rows
+        .iter()
+        .zip(matched.iter())
+        .filter(|(_, &matched)| !matched)
+        .map(|(row, _)| Ok(combine(Some(row), None)))
+        .collect(),
+      false => Vec::new(),
+    };
+    rows
+  });
+  Box::new(matches.chain(unmatched))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dataset::BadRows;
+
+  fn dataset(headers: &[&str], rows: &[&[&str]]) -> Dataset {
+    let rows: Vec<Result<StringRecord, Box<dyn Error>>> = rows
+      .iter()
+      .map(|row| Ok(StringRecord::from(row.to_vec())))
+      .collect();
+    Dataset {
+      headers: headers.iter().map(|&header| String::from(header)).collect(),
+      records: Box::new(rows.into_iter()),
+      bad_rows: Rc::new(RefCell::new(BadRows::default())),
+    }
+  }
+
+  fn collect(dataset: Dataset) -> Vec<Vec<String>> {
+    let mut rows = vec![dataset.headers];
+    for record in dataset.records {
+      rows.push(record.unwrap().iter().map(String::from).collect());
+    }
+    rows
+  }
+
+  fn join_datasets(join_type: JoinType, build_left: bool) -> Vec<Vec<String>> {
+    let left = dataset(
+      &["id", "name"],
+      &[&["1", "one"], &["2", "two"], &["", "none"], &["1", "uno"]],
+    );
+    let right = dataset(&["code", "name"], &[&["1", "I"], &["3", "III"], &["", "-"]]);
+    let condition = JoinCondition::parse("id = [b.code]").unwrap();
+    collect(join(left, right, &condition, join_type, build_left).unwrap())
+  }
+
+  #[test]
+  fn it_parses_join_conditions() {
+    assert_eq!(
+      Ok(JoinCondition(vec![
+        (String::from("field 1"), String::from("field2")),
+        (String::from("a.field3"), String::from("b.field3"))
+      ])),
+      JoinCondition::parse("[field 1] = field2 and a.field3=b.field3")
+    );
+    assert!(JoinCondition::parse("field1").is_err());
+  }
+
+  #[test]
+  fn it_joins_matching_rows() {
+    let expected = vec![
+      vec!["id", "a.name", "code", "b.name"],
+      vec!["1", "one", "1", "I"],
+      vec!["1", "uno", "1", "I"],
+    ];
+    assert_eq!(expected, join_datasets(JoinType::Inner, false));
+    assert_eq!(expected, join_datasets(JoinType::Inner, true));
+  }
+
+  #[test]
+  fn it_keeps_unmatched_rows_in_outer_joins() {
+    let left = vec![
+      vec!["id", "a.name", "code", "b.name"],
+      vec!["1", "one", "1", "I"],
+      vec!["2", "two", "", ""],
+      vec!["", "none", "", ""],
+      vec!["1", "uno", "1", "I"],
+    ];
+    assert_eq!(left, join_datasets(JoinType::Left, false));
+    let full = vec![
+      vec!["id", "a.name", "code", "b.name"],
+      vec!["1", "one", "1", "I"],
+      vec!["2", "two", "", ""],
+      vec!["", "none", "", ""],
+      vec!["1", "uno", "1", "I"],
+      vec!["", "", "3", "III"],
+      vec!["", "", "", "-"],
+    ];
+    assert_eq!(full, join_datasets(JoinType::Full, false));
+    let right = vec![
+      vec!["id", "a.name", "code", "b.name"],
+      vec!["1", "one", "1", "I"],
+      vec!["1", "uno", "1", "I"],
+      vec!["", "", "3", "III"],
+      vec!["", "", "", "-"],
+    ];
+    assert_eq!(right, join_datasets(JoinType::Right, true));
+  }
+
+  #[test]
+  fn it_keeps_or_drops_rows_in_another_dataset() {
+    let filter = KeyFilter {
+      column: String::from("id"),
+      filename: String::from("other.csv"),
+      other_column: String::from("code"),
+    };
+    let rows: &[&[&str]] = &[&["1", "one"], &["2", "two"], &["", "none"]];
+    let other = || dataset(&["code"], &[&["1"], &["3"], &[""]]);
+    let kept = semi_join(dataset(&["id", "name"], rows), other(), &filter, true).unwrap();
+    assert_eq!(vec![vec!["id", "name"], vec!["1", "one"]], collect(kept));
+    let dropped = semi_join(dataset(&["id", "name"], rows), other(), &filter, false).unwrap();
+    assert_eq!(
+      vec![vec!["id", "name"], vec!["2", "two"], vec!["", "none"]],
+      collect(dropped)
+    );
+  }
+
+  #[test]
+  fn it_rejects_unknown_join_columns() {
+    let left = dataset(&["id"], &[]);
+    let right = dataset(&["code"], &[]);
+    let condition = JoinCondition::parse("id = other").unwrap();
+    assert!(join(left, right, &condition, JoinType::Inner, false).is_err());
+  }
+}
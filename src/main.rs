@@ -2,16 +2,33 @@ use crate::args::Args;
 use crate::reader::run;
 use clap::Clap;
 
+mod aggregate;
 mod args;
+mod dataset;
+mod describe;
+mod distinct;
 mod errors;
+mod histogram;
+mod input;
+mod join;
+mod melt_parser;
+mod order_parser;
+mod output;
+mod pivot_parser;
+mod quantile;
 mod query_parser;
 mod reader;
 mod select_parser;
+mod sort;
+mod types;
 
 fn main() {
   let args = Args::parse();
   match run(&args, &mut std::io::stdout()) {
     Ok(()) => (),
-    Err(err) => eprintln!("{}", err),
+    Err(err) => match err.downcast_ref::<std::io::Error>() {
+      Some(err) if err.kind() == std::io::ErrorKind::BrokenPipe => (),
+      _ => eprintln!("{}", err),
+    },
   }
 }
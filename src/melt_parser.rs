@@ -0,0 +1,77 @@
+use crate::pivot_parser::parse_columns;
+use nom::branch::alt;
+use nom::combinator::{all_consuming, map};
+use nom::multi::many1;
+use nom::IResult;
+
+pub type ParsingError<'a> = nom::Err<nom::error::Error<&'a str>>;
+
+/// Turns each row into a row per value column, keyed by the `ids` columns.
+/// The value columns can be given as patterns like `Data.Temperature.*`, and
+/// are all the other columns if omitted.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Melt {
+  pub ids: Vec<String>,
+  pub values: Option<Vec<String>>,
+}
+
+enum Part {
+  Ids(Vec<String>),
+  Values(Vec<String>),
+}
+
+impl Melt {
+  pub fn parse(melt: &str) -> Result<Self, ParsingError<'_>> {
+    let (_, parts) = all_consuming(many1(Self::parse_part))(melt)?;
+    let mut ids = None;
+    let mut values = None;
+    for part in parts {
+      match part {
+        Part::Ids(columns) => ids = Some(columns),
+        Part::Values(columns) => values = Some(columns),
+      }
+    }
+    match ids {
+      Some(ids) => Ok(Melt { ids, values }),
+      None => Err(nom::Err::Error(nom::error::Error::new(
+        melt,
+        nom::error::ErrorKind::Verify,
+      ))),
+    }
+  }
+
+  fn parse_part(input: &str) -> IResult<&str, Part> {
+    alt((
+      map(parse_columns("id"), Part::Ids),
+      map(parse_columns("values"), Part::Values),
+    ))(input)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_parses_a_melt() {
+    assert_eq!(
+      Ok(Melt {
+        ids: vec![String::from("field 1"), String::from("field2")],
+        values: Some(vec![String::from("field3.*"), String::from("field 4")]),
+      }),
+      Melt::parse("id=[field 1],field2 values=field3.*, [field 4]")
+    );
+  }
+
+  #[test]
+  fn it_melts_all_the_other_columns_by_default() {
+    assert_eq!(
+      Ok(Melt {
+        ids: vec![String::from("field1")],
+        values: None,
+      }),
+      Melt::parse("id=field1")
+    );
+    assert!(Melt::parse("values=field1").is_err());
+  }
+}
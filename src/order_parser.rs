@@ -0,0 +1,184 @@
+use crate::select_parser::{Expression, SelectFields};
+use nom::branch::alt;
+use nom::bytes::complete::{tag, tag_no_case, take_while1};
+use nom::character::complete::{multispace0, multispace1};
+use nom::combinator::{all_consuming, map, opt};
+use nom::sequence::tuple;
+use nom::IResult;
+
+pub type ParsingError<'a> = nom::Err<nom::error::Error<&'a str>>;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SortKey {
+  pub field: String,
+  pub descending: bool,
+  pub nulls_first: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct OrderBy(pub Vec<SortKey>);
+
+impl OrderBy {
+  pub fn parse(order_by: &str) -> Result<Self, ParsingError<'_>> {
+    match all_consuming(Self::parse_order_by)(order_by) {
+      Ok((_, order_by)) => Ok(order_by),
+      Err(err) => Err(err),
+    }
+  }
+
+  fn parse_order_by(input: &str) -> IResult<&str, Self> {
+    alt((Self::parse_several_keys, Self::parse_one_key))(input)
+  }
+
+  fn parse_several_keys(input: &str) -> IResult<&str, Self> {
+    let (input, (key, _, mut keys)) =
+      tuple((Self::parse_key, tag(","), Self::parse_order_by))(input)?;
+    let mut new_keys = vec![key];
+    new_keys.append(&mut keys.0);
+    Ok((input, OrderBy(new_keys)))
+  }
+
+  fn parse_one_key(input: &str) -> IResult<&str, Self> {
+    let (input, key) = Self::parse_key(input)?;
+    Ok((input, OrderBy(vec![key])))
+  }
+
+  fn parse_key(input: &str) -> IResult<&str, SortKey> {
+    let (input, (field, direction, nulls)) = tuple((
+      Self::parse_field,
+      opt(Self::parse_direction),
+      opt(Self::parse_nulls),
+    ))(input)?;
+    let descending = direction == Some("desc");
+    Ok((
+      input,
+      SortKey {
+        field,
+        descending,
+        // Empty values are the greatest ones unless specified otherwise.
+        nulls_first: nulls.map_or(descending, |nulls| nulls == "first"),
+      },
+    ))
+  }
+
+  fn parse_direction(input: &str) -> IResult<&str, &str> {
+    let (input, (direction, _)) =
+      tuple((alt((tag_no_case("asc"), tag_no_case("desc"))), multispace0))(input)?;
+    Ok((
+      input,
+      if direction.eq_ignore_ascii_case("desc") {
+        "desc"
+      } else {
+        "asc"
+      },
+    ))
+  }
+
+  fn parse_nulls(input: &str) -> IResult<&str, &str> {
+    let (input, (_, _, nulls, _)) = tuple((
+      tag_no_case("nulls"),
+      multispace1,
+      alt((tag_no_case("first"), tag_no_case("last"))),
+      multispace0,
+    ))(input)?;
+    Ok((
+      input,
+      if nulls.eq_ignore_ascii_case("first") {
+        "first"
+      } else {
+        "last"
+      },
+    ))
+  }
+
+  fn parse_field(input: &str) -> IResult<&str, String> {
+    alt((
+      map(
+        tuple((SelectFields::parse_aggregate, multispace0)),
+        |(aggregate, _)| Expression::Aggregate(aggregate).to_string(),
+      ),
+      map(Self::parse_field_with_brackets, String::from),
+      map(Self::parse_field_without_brackets, String::from),
+    ))(input)
+  }
+
+  fn parse_field_without_brackets(input: &str) -> IResult<&str, &str> {
+    let (input, (_, field, _)) = tuple((
+      multispace0,
+      take_while1(|c| c != ' ' && c != ','),
+      multispace0,
+    ))(input)?;
+    Ok((input, field))
+  }
+
+  fn parse_field_with_brackets(input: &str) -> IResult<&str, &str> {
+    let (input, (_, _, field, _, _)) = tuple((
+      multispace0,
+      tag("["),
+      take_while1(|c| c != ']'),
+      tag("]"),
+      multispace0,
+    ))(input)?;
+    Ok((input, field))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn key(field: &str, descending: bool, nulls_first: bool) -> SortKey {
+    SortKey {
+      field: String::from(field),
+      descending,
+      nulls_first,
+    }
+  }
+
+  #[test]
+  fn it_parses_a_unique_key() {
+    assert_eq!(
+      Ok(OrderBy(vec![key("field1", false, false)])),
+      OrderBy::parse("field1")
+    );
+  }
+
+  #[test]
+  fn it_parses_directions() {
+    assert_eq!(
+      Ok(OrderBy(vec![
+        key("field 1", true, true),
+        key("field2", false, false),
+        key("field3", false, false)
+      ])),
+      OrderBy::parse("[field 1] desc, field2 ASC,field3")
+    );
+  }
+
+  #[test]
+  fn it_parses_nulls_placement() {
+    assert_eq!(
+      Ok(OrderBy(vec![
+        key("field 1", true, false),
+        key("field2", false, true)
+      ])),
+      OrderBy::parse("[field 1] desc nulls last, field2 nulls first")
+    );
+  }
+
+  #[test]
+  fn it_parses_aggregates() {
+    assert_eq!(
+      Ok(OrderBy(vec![
+        key("avg(Data.Temperature.Avg Temp)", true, true),
+        key("count(distinct x)", false, false)
+      ])),
+      OrderBy::parse("avg([Data.Temperature.Avg Temp]) desc, count(distinct x)")
+    );
+  }
+
+  #[test]
+  fn it_rejects_unknown_directions() {
+    assert!(OrderBy::parse("field1 up").is_err());
+  }
+}
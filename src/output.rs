@@ -0,0 +1,1081 @@
+use crate::errors::AppError;
+use crate::types::{parse_boolean, parse_float, ColumnType};
+use crate::Args;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tabular::{Row, Table};
+use tempfile::Builder;
+use unicode_width::UnicodeWidthStr;
+
+pub const DEFAULT_SAMPLE_ROWS: usize = 1000;
+pub const DEFAULT_BATCH_SIZE: usize = 100;
+
+pub trait Renderer {
+  fn write_headers(&mut self, headers: &[String]) -> Result<(), Box<dyn Error>>;
+  fn write_row(&mut self, row: &[String]) -> Result<(), Box<dyn Error>>;
+  fn finish(&mut self) -> Result<(), Box<dyn Error>>;
+
+  /// Whether the renderer won't accept more rows, so that the reading can stop.
+  fn is_done(&self) -> bool {
+    false
+  }
+}
+
+/// The rows to output: `offset` rows are skipped, then at most `limit` rows
+/// are kept, of which only the last `tail` ones are kept.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Window {
+  pub offset: usize,
+  pub limit: Option<usize>,
+  pub tail: Option<usize>,
+}
+
+impl Window {
+  pub fn is_empty(&self) -> bool {
+    self.offset == 0 && self.limit.is_none() && self.tail.is_none()
+  }
+
+  /// Whether the given number of rows is enough to fill the window.
+  pub fn is_full(&self, rows: usize) -> bool {
+    self
+      .limit
+      .is_some_and(|limit| rows >= self.offset.saturating_add(limit))
+  }
+
+  /// Number of rows needed to fill the window, if limited.
+  pub fn top(&self) -> Option<usize> {
+    match self.tail {
+      Some(_) => None,
+      None => self.limit.map(|limit| self.offset.saturating_add(limit)),
+    }
+  }
+
+  /// Number of rows in the window, out of the given number of rows.
+  pub fn count(&self, rows: usize) -> usize {
+    let mut count = rows.saturating_sub(self.offset);
+    if let Some(limit) = self.limit {
+      count = std::cmp::min(count, limit);
+    }
+    if let Some(tail) = self.tail {
+      count = std::cmp::min(count, tail);
+    }
+    count
+  }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+  Table,
+  Csv,
+  Json,
+  Ndjson,
+  Markdown,
+  Html,
+  Sql,
+}
+
+impl OutputFormat {
+  pub fn from_extension(filename: &str) -> Option<Self> {
+    match Path::new(filename).extension()?.to_str()? {
+      "txt" => Some(OutputFormat::Table),
+      "csv" => Some(OutputFormat::Csv),
+      "json" => Some(OutputFormat::Json),
+      "ndjson" | "jsonl" => Some(OutputFormat::Ndjson),
+      "md" => Some(OutputFormat::Markdown),
+      "html" | "htm" => Some(OutputFormat::Html),
+      "sql" => Some(OutputFormat::Sql),
+      _ => None,
+    }
+  }
+}
+
+impl FromStr for OutputFormat {
+  type Err = AppError;
+
+  fn from_str(format: &str) -> Result<Self, Self::Err> {
+    match format {
+      "table" => Ok(OutputFormat::Table),
+      "csv" => Ok(OutputFormat::Csv),
+      "json" => Ok(OutputFormat::Json),
+      "ndjson" => Ok(OutputFormat::Ndjson),
+      "markdown" | "md" => Ok(OutputFormat::Markdown),
+      "html" => Ok(OutputFormat::Html),
+      "sql" => Ok(OutputFormat::Sql),
+      _ => Err(AppError::InvalidOutputFormat(String::from(format))),
+    }
+  }
+}
+
+pub fn renderer<'a>(
+  args: &Args,
+  writer: &'a mut dyn Write,
+) -> Result<Box<dyn Renderer + 'a>, AppError> {
+  let window = args.window();
+  let renderer = format_renderer(args, writer)?;
+  if window.is_empty() {
+    return Ok(renderer);
+  }
+  Ok(Box::new(WindowRenderer {
+    renderer,
+    window,
+    rows: 0,
+    tail: VecDeque::new(),
+  }))
+}
+
+fn format_renderer<'a>(
+  args: &Args,
+  writer: &'a mut dyn Write,
+) -> Result<Box<dyn Renderer + 'a>, AppError> {
+  let sample_rows = args.sample_rows.unwrap_or(DEFAULT_SAMPLE_ROWS);
+  let format = args.output_format();
+  Ok(match format {
+    OutputFormat::Table if args.exact_widths => Box::new(TableRenderer {
+      writer,
+      table: None,
+    }),
+    OutputFormat::Table => Box::new(StreamingTableRenderer {
+      writer,
+      sample_size: sample_rows,
+      sample: Vec::new(),
+      widths: None,
+    }),
+    OutputFormat::Csv => Box::new(CsvRenderer {
+      writer: csv::WriterBuilder::new()
+        .delimiter(args.parse_output_delimiter()?)
+        .from_writer(writer),
+    }),
+    OutputFormat::Json | OutputFormat::Ndjson => Box::new(JsonRenderer {
+      writer,
+      lines: format == OutputFormat::Ndjson,
+      headers: Vec::new(),
+      inference: TypeInference::new(args.typed, sample_rows),
+      count: 0,
+    }),
+    OutputFormat::Sql => Box::new(SqlRenderer {
+      writer,
+      table_name: match &args.table_name {
+        Some(table_name) => table_name.clone(),
+        None => return Err(AppError::MissingTableName),
+      },
+      batch_size: args.batch_size.unwrap_or(DEFAULT_BATCH_SIZE),
+      temp_dir: args
+        .temp_dir
+        .as_ref()
+        .map_or_else(std::env::temp_dir, PathBuf::from),
+      headers: Vec::new(),
+      types: Vec::new(),
+      rows: None,
+    }),
+    OutputFormat::Markdown => Box::new(MarkdownRenderer { writer }),
+    OutputFormat::Html => Box::new(HtmlRenderer { writer }),
+  })
+}
+
+/// Writes to a temporary file next to the given path, then renames it, so that
+/// the file is never left half-written.
+pub fn write_atomically(
+  path: &str,
+  write: impl FnOnce(&mut BufWriter<&mut File>) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+  let directory = match Path::new(path).parent() {
+    Some(directory) if directory != Path::new("") => directory,
+    _ => Path::new("."),
+  };
+  let mut builder = Builder::new();
+  // Like any new file, and unlike the usual temporary files, the output can be
+  // read by others unless the umask says otherwise.
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    builder.permissions(std::fs::Permissions::from_mode(0o666));
+  }
+  let mut file = builder.tempfile_in(directory)?;
+  {
+    let mut writer = BufWriter::new(file.as_file_mut());
+    write(&mut writer)?;
+    writer.flush()?;
+  }
+  // A file that is replaced keeps its permissions.
+  if let Ok(metadata) = std::fs::metadata(path) {
+    file.as_file().set_permissions(metadata.permissions())?;
+  }
+  file.persist(path)?;
+  Ok(())
+}
+
+/// Passes the rows of the window to another renderer, keeping the last ones in
+/// a ring buffer when only the tail is wanted.
+struct WindowRenderer<'a> {
+  renderer: Box<dyn Renderer + 'a>,
+  window: Window,
+  rows: usize,
+  tail: VecDeque<Vec<String>>,
+}
+
+impl<'a> Renderer for WindowRenderer<'a> {
+  fn write_headers(&mut self, headers: &[String]) -> Result<(), Box<dyn Error>> {
+    self.renderer.write_headers(headers)
+  }
+
+  fn write_row(&mut self, row: &[String]) -> Result<(), Box<dyn Error>> {
+    if self.is_done() {
+      return Ok(());
+    }
+    self.rows += 1;
+    if self.rows <= self.window.offset {
+      return Ok(());
+    }
+    match self.window.tail {
+      Some(0) => Ok(()),
+      Some(tail) => {
+        if self.tail.len() == tail {
+          self.tail.pop_front();
+        }
+        self.tail.push_back(row.to_vec());
+        Ok(())
+      }
+      None => self.renderer.write_row(row),
+    }
+  }
+
+  fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+    for row in std::mem::take(&mut self.tail) {
+      self.renderer.write_row(&row)?;
+    }
+    self.renderer.finish()
+  }
+
+  fn is_done(&self) -> bool {
+    self.window.is_full(self.rows)
+  }
+}
+
+/// Buffers all the rows to display them with exact column widths.
+struct TableRenderer<'a> {
+  writer: &'a mut dyn Write,
+  table: Option<Table>,
+}
+
+impl<'a> Renderer for TableRenderer<'a> {
+  fn write_headers(&mut self, headers: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut table = Table::new(&row_spec(headers));
+    table.add_row(table_row(headers));
+    self.table = Some(table);
+    Ok(())
+  }
+
+  fn write_row(&mut self, row: &[String]) -> Result<(), Box<dyn Error>> {
+    if let Some(table) = &mut self.table {
+      table.add_row(table_row(row));
+    }
+    Ok(())
+  }
+
+  fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+    if let Some(table) = &self.table {
+      write!(self.writer, "{}", table)?;
+    }
+    Ok(())
+  }
+}
+
+/// Computes the column widths from the first rows, then writes the rows as
+/// soon as they are received. Cells wider than their column in the following
+/// rows are not truncated.
+struct StreamingTableRenderer<'a> {
+  writer: &'a mut dyn Write,
+  sample_size: usize,
+  sample: Vec<Vec<String>>,
+  widths: Option<Vec<usize>>,
+}
+
+impl<'a> StreamingTableRenderer<'a> {
+  fn flush_sample(&mut self) -> Result<(), Box<dyn Error>> {
+    let mut widths = vec![0; self.sample.first().map_or(0, Vec::len)];
+    for row in &self.sample {
+      for (width, cell) in widths.iter_mut().zip(row) {
+        *width = std::cmp::max(*width, cell.width());
+      }
+    }
+    for row in &self.sample {
+      write_line(self.writer, row, &widths)?;
+    }
+    self.sample.clear();
+    self.widths = Some(widths);
+    Ok(())
+  }
+}
+
+impl<'a> Renderer for StreamingTableRenderer<'a> {
+  fn write_headers(&mut self, headers: &[String]) -> Result<(), Box<dyn Error>> {
+    self.sample.push(headers.to_vec());
+    Ok(())
+  }
+
+  fn write_row(&mut self, row: &[String]) -> Result<(), Box<dyn Error>> {
+    match &self.widths {
+      Some(widths) => write_line(self.writer, row, widths)?,
+      None => {
+        self.sample.push(row.to_vec());
+        if self.sample.len() > self.sample_size {
+          self.flush_sample()?;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+    if self.widths.is_none() {
+      self.flush_sample()?;
+    }
+    Ok(())
+  }
+}
+
+struct CsvRenderer<'a> {
+  writer: csv::Writer<&'a mut dyn Write>,
+}
+
+impl<'a> Renderer for CsvRenderer<'a> {
+  fn write_headers(&mut self, headers: &[String]) -> Result<(), Box<dyn Error>> {
+    self.writer.write_record(headers)?;
+    Ok(())
+  }
+
+  fn write_row(&mut self, row: &[String]) -> Result<(), Box<dyn Error>> {
+    self.writer.write_record(row)?;
+    Ok(())
+  }
+
+  fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+    self.writer.flush()?;
+    Ok(())
+  }
+}
+
+/// Buffers the first rows until the column types can be inferred from them.
+/// When not typed, all the columns are strings and nothing is buffered.
+struct TypeInference {
+  typed: bool,
+  sample_size: usize,
+  sample: Vec<Vec<String>>,
+  types: Option<Vec<ColumnType>>,
+}
+
+impl TypeInference {
+  fn new(typed: bool, sample_size: usize) -> Self {
+    TypeInference {
+      typed,
+      sample_size,
+      sample: Vec::new(),
+      types: None,
+    }
+  }
+
+  fn start(&mut self, columns: usize) {
+    if !self.typed {
+      self.types = Some(vec![ColumnType::String; columns]);
+    }
+  }
+
+  /// Returns the rows that can be written, i.e. none while the sample is
+  /// being collected, then the whole sample once the types are known.
+  fn push(&mut self, row: &[String]) -> Vec<Vec<String>> {
+    if self.types.is_some() {
+      return vec![row.to_vec()];
+    }
+    self.sample.push(row.to_vec());
+    if self.sample.len() >= self.sample_size {
+      self.flush()
+    } else {
+      Vec::new()
+    }
+  }
+
+  fn flush(&mut self) -> Vec<Vec<String>> {
+    if self.types.is_none() {
+      let columns = self.sample.first().map_or(0, Vec::len);
+      self.types = Some(
+        (0..columns)
+          .map(|i| ColumnType::infer(self.sample.iter().map(|row| row[i].as_str())))
+          .collect(),
+      );
+    }
+    std::mem::take(&mut self.sample)
+  }
+
+  fn types(&self) -> &[ColumnType] {
+    self.types.as_deref().unwrap_or(&[])
+  }
+}
+
+/// Writes the rows as JSON objects, either in an array or one per line. When
+/// typed, the values are converted according to the column types inferred from
+/// the first rows.
+struct JsonRenderer<'a> {
+  writer: &'a mut dyn Write,
+  lines: bool,
+  headers: Vec<String>,
+  inference: TypeInference,
+  count: usize,
+}
+
+impl<'a> JsonRenderer<'a> {
+  fn write_object(&mut self, row: &[String]) -> Result<(), Box<dyn Error>> {
+    if !self.lines {
+      self
+        .writer
+        .write_all(if self.count == 0 { b"[\n  " } else { b",\n  " })?;
+    }
+    self.writer.write_all(b"{")?;
+    let types = self.inference.types();
+    for (i, (header, value)) in self.headers.iter().zip(row).enumerate() {
+      if i > 0 {
+        self.writer.write_all(b",")?;
+      }
+      serde_json::to_writer(&mut self.writer, header)?;
+      self.writer.write_all(b":")?;
+      let column_type = types.get(i).copied().unwrap_or(ColumnType::String);
+      serde_json::to_writer(&mut self.writer, &json_value(value, column_type))?;
+    }
+    self.writer.write_all(b"}")?;
+    if self.lines {
+      self.writer.write_all(b"\n")?;
+    }
+    self.count += 1;
+    Ok(())
+  }
+}
+
+impl<'a> Renderer for JsonRenderer<'a> {
+  fn write_headers(&mut self, headers: &[String]) -> Result<(), Box<dyn Error>> {
+    self.headers = headers.to_vec();
+    self.inference.start(headers.len());
+    Ok(())
+  }
+
+  fn write_row(&mut self, row: &[String]) -> Result<(), Box<dyn Error>> {
+    for row in self.inference.push(row) {
+      self.write_object(&row)?;
+    }
+    Ok(())
+  }
+
+  fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+    for row in self.inference.flush() {
+      self.write_object(&row)?;
+    }
+    if !self.lines {
+      self
+        .writer
+        .write_all(if self.count == 0 { b"[]\n" } else { b"\n]\n" })?;
+    }
+    Ok(())
+  }
+}
+
+/// Writes a `CREATE TABLE` statement, with the column types inferred from all
+/// the rows, followed by `INSERT` statements of `batch_size` rows each. The
+/// rows are written to a temporary file until the types are known.
+struct SqlRenderer<'a> {
+  writer: &'a mut dyn Write,
+  table_name: String,
+  batch_size: usize,
+  temp_dir: PathBuf,
+  headers: Vec<String>,
+  types: Vec<ColumnType>,
+  rows: Option<csv::Writer<File>>,
+}
+
+impl<'a> SqlRenderer<'a> {
+  fn write_create_table(&mut self) -> Result<(), Box<dyn Error>> {
+    writeln!(
+      self.writer,
+      "CREATE TABLE {} (",
+      quote_identifier(&self.table_name)
+    )?;
+    for (i, header) in self.headers.iter().enumerate() {
+      writeln!(
+        self.writer,
+        "  {} {}{}",
+        quote_identifier(header),
+        sql_type(self.types[i]),
+        if i + 1 < self.headers.len() { "," } else { "" }
+      )?;
+    }
+    writeln!(self.writer, ");")?;
+    Ok(())
+  }
+}
+
+impl<'a> Renderer for SqlRenderer<'a> {
+  fn write_headers(&mut self, headers: &[String]) -> Result<(), Box<dyn Error>> {
+    self.headers = headers.to_vec();
+    self.types = vec![ColumnType::Null; headers.len()];
+    self.rows = Some(csv::Writer::from_writer(tempfile::tempfile_in(
+      &self.temp_dir,
+    )?));
+    Ok(())
+  }
+
+  fn write_row(&mut self, row: &[String]) -> Result<(), Box<dyn Error>> {
+    for (column_type, value) in self.types.iter_mut().zip(row) {
+      *column_type = column_type.merge(ColumnType::of(value));
+    }
+    if let Some(rows) = &mut self.rows {
+      rows.write_record(row)?;
+    }
+    Ok(())
+  }
+
+  fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+    let mut file = match self.rows.take() {
+      Some(rows) => rows.into_inner()?,
+      None => return Ok(()),
+    };
+    file.seek(SeekFrom::Start(0))?;
+    self.write_create_table()?;
+    let insert = format!(
+      "INSERT INTO {} ({}) VALUES",
+      quote_identifier(&self.table_name),
+      self
+        .headers
+        .iter()
+        .map(|header| quote_identifier(header))
+        .collect::<Vec<String>>()
+        .join(", ")
+    );
+    let mut batch_count = 0;
+    let reader = csv::ReaderBuilder::new()
+      .has_headers(false)
+      .from_reader(BufReader::new(file));
+    for row in reader.into_records() {
+      let row = row?;
+      if batch_count == 0 {
+        writeln!(self.writer, "{}", insert)?;
+      } else {
+        writeln!(self.writer, ",")?;
+      }
+      let values: Vec<String> = row
+        .iter()
+        .zip(&self.types)
+        .map(|(value, &column_type)| sql_value(value, column_type))
+        .collect();
+      write!(self.writer, "  ({})", values.join(", "))?;
+      batch_count += 1;
+      if batch_count >= self.batch_size {
+        writeln!(self.writer, ";")?;
+        batch_count = 0;
+      }
+    }
+    if batch_count > 0 {
+      writeln!(self.writer, ";")?;
+    }
+    Ok(())
+  }
+}
+
+fn quote_identifier(identifier: &str) -> String {
+  format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn sql_type(column_type: ColumnType) -> &'static str {
+  match column_type {
+    ColumnType::Boolean => "BOOLEAN",
+    ColumnType::Integer => "BIGINT",
+    ColumnType::Float => "DOUBLE PRECISION",
+    ColumnType::Null | ColumnType::String => "TEXT",
+  }
+}
+
+fn sql_value(value: &str, column_type: ColumnType) -> String {
+  match json_value(value, column_type) {
+    Value::Null => String::from("NULL"),
+    Value::Bool(true) => String::from("TRUE"),
+    Value::Bool(false) => String::from("FALSE"),
+    Value::Number(number) => number.to_string(),
+    _ => format!("'{}'", value.replace('\'', "''")),
+  }
+}
+
+struct MarkdownRenderer<'a> {
+  writer: &'a mut dyn Write,
+}
+
+impl<'a> MarkdownRenderer<'a> {
+  fn write_line(&mut self, cells: &[String]) -> Result<(), Box<dyn Error>> {
+    for cell in cells {
+      let cell = cell
+        .replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace("\r\n", "<br>")
+        .replace('\n', "<br>");
+      write!(self.writer, "| {} ", cell)?;
+    }
+    writeln!(self.writer, "|")?;
+    Ok(())
+  }
+}
+
+impl<'a> Renderer for MarkdownRenderer<'a> {
+  fn write_headers(&mut self, headers: &[String]) -> Result<(), Box<dyn Error>> {
+    self.write_line(headers)?;
+    self.write_line(&vec![String::from("---"); headers.len()])
+  }
+
+  fn write_row(&mut self, row: &[String]) -> Result<(), Box<dyn Error>> {
+    self.write_line(row)
+  }
+
+  fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+    Ok(())
+  }
+}
+
+struct HtmlRenderer<'a> {
+  writer: &'a mut dyn Write,
+}
+
+impl<'a> HtmlRenderer<'a> {
+  fn write_line(&mut self, cells: &[String], tag: &str) -> Result<(), Box<dyn Error>> {
+    write!(self.writer, "    <tr>")?;
+    for cell in cells {
+      write!(self.writer, "<{}>{}</{}>", tag, escape_html(cell), tag)?;
+    }
+    writeln!(self.writer, "</tr>")?;
+    Ok(())
+  }
+}
+
+impl<'a> Renderer for HtmlRenderer<'a> {
+  fn write_headers(&mut self, headers: &[String]) -> Result<(), Box<dyn Error>> {
+    writeln!(self.writer, "<table>\n  <thead>")?;
+    self.write_line(headers, "th")?;
+    writeln!(self.writer, "  </thead>\n  <tbody>")?;
+    Ok(())
+  }
+
+  fn write_row(&mut self, row: &[String]) -> Result<(), Box<dyn Error>> {
+    self.write_line(row, "td")
+  }
+
+  fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+    writeln!(self.writer, "  </tbody>\n</table>")?;
+    Ok(())
+  }
+}
+
+fn escape_html(value: &str) -> String {
+  let mut escaped = String::with_capacity(value.len());
+  for c in value.chars() {
+    match c {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      '\'' => escaped.push_str("&#39;"),
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+fn json_value(value: &str, column_type: ColumnType) -> Value {
+  let typed_value = match column_type {
+    ColumnType::String => None,
+    _ if value.is_empty() => Some(Value::Null),
+    ColumnType::Boolean => parse_boolean(value).map(Value::from),
+    // A numeric column may have decimal values after the sample.
+    ColumnType::Integer | ColumnType::Float => match value.parse::<i64>() {
+      Ok(integer) => Some(Value::from(integer)),
+      Err(_) => parse_float(value).map(Value::from),
+    },
+    ColumnType::Null => None,
+  };
+  typed_value.unwrap_or_else(|| Value::from(value))
+}
+
+fn write_line(writer: &mut dyn Write, row: &[String], widths: &[usize]) -> std::io::Result<()> {
+  for (i, cell) in row.iter().enumerate() {
+    writer.write_all(cell.as_bytes())?;
+    if i + 1 < row.len() {
+      let padding = widths
+        .get(i)
+        .map_or(0, |width| width.saturating_sub(cell.width()));
+      write!(writer, "{:1$}", " ", padding + 1)?;
+    }
+  }
+  writeln!(writer)
+}
+
+fn row_spec(headers: &[String]) -> String {
+  headers
+    .iter()
+    .map(|_| String::from("{:<}"))
+    .collect::<Vec<String>>()
+    .join(" ")
+}
+
+fn table_row(cells: &[String]) -> Row {
+  let mut row = Row::new();
+  for cell in cells {
+    row.add_cell(cell);
+  }
+  row
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn render(args: &Args, rows: &[&[&str]]) -> Result<String, Box<dyn Error>> {
+    let mut out = Vec::new();
+    {
+      let mut renderer = renderer(args, &mut out)?;
+      let rows: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| row.iter().map(|cell| String::from(*cell)).collect())
+        .collect();
+      renderer.write_headers(&rows[0])?;
+      for row in &rows[1..] {
+        renderer.write_row(row)?;
+      }
+      renderer.finish()?;
+    }
+    Ok(String::from_utf8(out)?)
+  }
+
+  const ROWS: &[&[&str]] = &[
+    &["city", "country"],
+    &["Paris", "France"],
+    &["Zürich", "Switzerland"],
+    &["Llanfairpwllgwyngyll", "UK"],
+  ];
+
+  #[test]
+  fn it_renders_the_same_table_when_streaming_or_not() -> Result<(), Box<dyn Error>> {
+    let expected = "
+city                 country
+Paris                France
+Zürich               Switzerland
+Llanfairpwllgwyngyll UK
+";
+    let exact_widths = Args {
+      exact_widths: true,
+      ..Default::default()
+    };
+    assert_eq!(expected.trim_start(), render(&exact_widths, ROWS)?);
+    assert_eq!(expected.trim_start(), render(&Args::default(), ROWS)?);
+    Ok(())
+  }
+
+  #[test]
+  fn it_computes_widths_from_the_first_rows_when_streaming() -> Result<(), Box<dyn Error>> {
+    let args = Args {
+      sample_rows: Some(2),
+      ..Default::default()
+    };
+    assert_eq!(
+      "
+city   country
+Paris  France
+Zürich Switzerland
+Llanfairpwllgwyngyll UK
+"
+      .trim_start(),
+      render(&args, ROWS)?
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn it_renders_csv() -> Result<(), Box<dyn Error>> {
+    let args = Args {
+      output: Some(OutputFormat::Csv),
+      output_delimiter: Some(';'),
+      ..Default::default()
+    };
+    assert_eq!(
+      "name;quote\nJoe;\"Hello; \"\"world\"\"\"\n",
+      render(&args, &[&["name", "quote"], &["Joe", "Hello; \"world\""]])?
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn it_renders_json() -> Result<(), Box<dyn Error>> {
+    let args = Args {
+      output: Some(OutputFormat::Json),
+      ..Default::default()
+    };
+    assert_eq!(
+      "[\n  {\"city\":\"Paris\",\"temp\":\"12\"},\n  {\"city\":\"Z\\\"urich\",\"temp\":\"\"}\n]\n",
+      render(
+        &args,
+        &[&["city", "temp"], &["Paris", "12"], &["Z\"urich", ""]]
+      )?
+    );
+    assert_eq!("[]\n", render(&args, &[&["city", "temp"]])?);
+    Ok(())
+  }
+
+  #[test]
+  fn it_renders_typed_ndjson() -> Result<(), Box<dyn Error>> {
+    let args = Args {
+      output: Some(OutputFormat::Ndjson),
+      typed: true,
+      sample_rows: Some(2),
+      ..Default::default()
+    };
+    assert_eq!(
+      "
+{\"city\":\"Paris\",\"temp\":12,\"rain\":true,\"zip\":\"75001\"}
+{\"city\":\"Oslo\",\"temp\":-2.5,\"rain\":null,\"zip\":\"0150\"}
+{\"city\":\"Rome\",\"temp\":\"n/a\",\"rain\":false,\"zip\":\"00118\"}
+"
+      .trim_start(),
+      render(
+        &args,
+        &[
+          &["city", "temp", "rain", "zip"],
+          &["Paris", "12", "true", "75001"],
+          &["Oslo", "-2.5", "", "0150"],
+          &["Rome", "n/a", "false", "00118"]
+        ]
+      )?
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn it_keeps_numbers_after_the_sample_numeric() -> Result<(), Box<dyn Error>> {
+    let args = Args {
+      output: Some(OutputFormat::Ndjson),
+      typed: true,
+      sample_rows: Some(1),
+      ..Default::default()
+    };
+    assert_eq!(
+      "
+{\"v\":1}
+{\"v\":2.5}
+{\"v\":\"x\"}
+"
+      .trim_start(),
+      render(&args, &[&["v"], &["1"], &["2.5"], &["x"]])?
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn it_renders_markdown() -> Result<(), Box<dyn Error>> {
+    let args = Args {
+      output: Some(OutputFormat::Markdown),
+      ..Default::default()
+    };
+    assert_eq!(
+      "
+| command | description |
+| --- | --- |
+| a \\| b | pipes the output<br>of a to b |
+"
+      .trim_start(),
+      render(
+        &args,
+        &[
+          &["command", "description"],
+          &["a | b", "pipes the output\nof a to b"]
+        ]
+      )?
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn it_renders_html() -> Result<(), Box<dyn Error>> {
+    let args = Args {
+      output: Some(OutputFormat::Html),
+      ..Default::default()
+    };
+    assert_eq!(
+      "
+<table>
+  <thead>
+    <tr><th>name</th><th>quote</th></tr>
+  </thead>
+  <tbody>
+    <tr><td>Joe</td><td>&lt;b&gt;&quot;Tom &amp; Jerry&quot;&lt;/b&gt;</td></tr>
+  </tbody>
+</table>
+"
+      .trim_start(),
+      render(
+        &args,
+        &[&["name", "quote"], &["Joe", "<b>\"Tom & Jerry\"</b>"]]
+      )?
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn it_renders_sql() -> Result<(), Box<dyn Error>> {
+    let args = Args {
+      output: Some(OutputFormat::Sql),
+      table_name: Some(String::from("cities")),
+      batch_size: Some(2),
+      ..Default::default()
+    };
+    assert_eq!(
+      "
+CREATE TABLE \"cities\" (
+  \"name\" TEXT,
+  \"temp\" DOUBLE PRECISION,
+  \"is \"\"big\"\"\" BOOLEAN
+);
+INSERT INTO \"cities\" (\"name\", \"temp\", \"is \"\"big\"\"\") VALUES
+  ('Paris', 12, TRUE),
+  ('L''Aquila', -2.5, NULL);
+INSERT INTO \"cities\" (\"name\", \"temp\", \"is \"\"big\"\"\") VALUES
+  ('Oslo', 3, FALSE);
+"
+      .trim_start(),
+      render(
+        &args,
+        &[
+          &["name", "temp", "is \"big\""],
+          &["Paris", "12", "true"],
+          &["L'Aquila", "-2.5", ""],
+          &["Oslo", "3", "false"]
+        ]
+      )?
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn it_infers_sql_types_from_all_the_rows() -> Result<(), Box<dyn Error>> {
+    let args = Args {
+      output: Some(OutputFormat::Sql),
+      table_name: Some(String::from("t")),
+      sample_rows: Some(2),
+      ..Default::default()
+    };
+    assert_eq!(
+      "
+CREATE TABLE \"t\" (
+  \"v\" TEXT,
+  \"w\" DOUBLE PRECISION
+);
+INSERT INTO \"t\" (\"v\", \"w\") VALUES
+  ('1', 1),
+  ('2', 2),
+  ('2.5', 2.5),
+  ('x', NULL);
+"
+      .trim_start(),
+      render(
+        &args,
+        &[
+          &["v", "w"],
+          &["1", "1"],
+          &["2", "2"],
+          &["2.5", "2.5"],
+          &["x", ""]
+        ]
+      )?
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn it_requires_a_table_name_for_sql() {
+    let args = Args {
+      output: Some(OutputFormat::Sql),
+      ..Default::default()
+    };
+    assert!(renderer(&args, &mut Vec::new()).is_err());
+  }
+
+  #[test]
+  fn it_infers_the_format_from_the_output_file() {
+    let args = Args {
+      output_file: Some(String::from("out/result.md")),
+      ..Default::default()
+    };
+    assert_eq!(OutputFormat::Markdown, args.output_format());
+    let args = Args {
+      output: Some(OutputFormat::Csv),
+      output_file: Some(String::from("result.json")),
+      ..Default::default()
+    };
+    assert_eq!(OutputFormat::Csv, args.output_format());
+    assert_eq!(OutputFormat::Table, Args::default().output_format());
+  }
+
+  #[test]
+  fn it_writes_files_atomically() -> Result<(), Box<dyn Error>> {
+    let directory = tempfile::tempdir()?;
+    let path = directory.path().join("result.csv");
+    let path = path.to_str().unwrap();
+    write_atomically(path, |writer| {
+      writer.write_all(b"a,b\n")?;
+      Ok(())
+    })?;
+    assert_eq!("a,b\n", std::fs::read_to_string(path)?);
+    let failed = write_atomically(path, |writer| {
+      writer.write_all(b"c,d\n")?;
+      Err(Box::new(AppError::MissingTableName))
+    });
+    assert!(failed.is_err());
+    assert_eq!("a,b\n", std::fs::read_to_string(path)?);
+    assert_eq!(1, std::fs::read_dir(directory.path())?.count());
+    Ok(())
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn it_keeps_the_permissions_of_files() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::PermissionsExt;
+    let directory = tempfile::tempdir()?;
+    let mode = |path: &Path| -> std::io::Result<u32> {
+      Ok(std::fs::metadata(path)?.permissions().mode() & 0o777)
+    };
+    let created = directory.path().join("created.csv");
+    File::create(&created)?;
+    let path = directory.path().join("result.csv");
+    write_atomically(path.to_str().unwrap(), |_| Ok(()))?;
+    assert_eq!(mode(&created)?, mode(&path)?);
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640))?;
+    write_atomically(path.to_str().unwrap(), |_| Ok(()))?;
+    assert_eq!(0o640, mode(&path)?);
+    Ok(())
+  }
+
+  #[test]
+  fn it_renders_a_window_of_rows() -> Result<(), Box<dyn Error>> {
+    let rows: &[&[&str]] = &[&["n"], &["1"], &["2"], &["3"], &["4"], &["5"]];
+    let window = |offset, limit, tail| Args {
+      output: Some(OutputFormat::Csv),
+      offset,
+      limit,
+      tail,
+      ..Default::default()
+    };
+    assert_eq!("n\n1\n2\n", render(&window(None, Some(2), None), rows)?);
+    assert_eq!("n\n2\n3\n", render(&window(Some(1), Some(2), None), rows)?);
+    assert_eq!("n\n4\n5\n", render(&window(None, None, Some(2)), rows)?);
+    assert_eq!(
+      "n\n3\n4\n",
+      render(&window(Some(1), Some(3), Some(2)), rows)?
+    );
+    assert_eq!("n\n", render(&window(Some(10), None, None), rows)?);
+    Ok(())
+  }
+}
@@ -0,0 +1,141 @@
+use crate::select_parser::{Aggregate, AggregateFunction, Expression, SelectFields};
+use nom::branch::alt;
+use nom::bytes::complete::{tag, tag_no_case};
+use nom::character::complete::multispace0;
+use nom::combinator::{all_consuming, map};
+use nom::multi::many1;
+use nom::sequence::tuple;
+use nom::IResult;
+
+pub type ParsingError<'a> = nom::Err<nom::error::Error<&'a str>>;
+
+/// A pivot table, with a row for each distinct value of the `rows` columns, a
+/// column for each distinct value of the `cols` columns, and the `value`
+/// aggregate in each cell.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Pivot {
+  pub rows: Vec<String>,
+  pub cols: Vec<String>,
+  pub value: Aggregate,
+}
+
+enum Part {
+  Rows(Vec<String>),
+  Cols(Vec<String>),
+  Value(Aggregate),
+}
+
+impl Pivot {
+  pub fn parse(pivot: &str) -> Result<Self, ParsingError<'_>> {
+    let (_, parts) = all_consuming(many1(Self::parse_part))(pivot)?;
+    let mut rows = None;
+    let mut cols = None;
+    let mut value = None;
+    for part in parts {
+      match part {
+        Part::Rows(columns) => rows = Some(columns),
+        Part::Cols(columns) => cols = Some(columns),
+        Part::Value(aggregate) => value = Some(aggregate),
+      }
+    }
+    match (rows, cols) {
+      (Some(rows), Some(cols)) => Ok(Pivot {
+        rows,
+        cols,
+        value: value.unwrap_or(Aggregate {
+          function: AggregateFunction::Count,
+          field: None,
+        }),
+      }),
+      _ => Err(nom::Err::Error(nom::error::Error::new(
+        pivot,
+        nom::error::ErrorKind::Verify,
+      ))),
+    }
+  }
+
+  fn parse_part(input: &str) -> IResult<&str, Part> {
+    alt((
+      map(parse_columns("rows"), Part::Rows),
+      map(parse_columns("cols"), Part::Cols),
+      map(
+        tuple((
+          multispace0,
+          tag_no_case("value"),
+          multispace0,
+          tag("="),
+          SelectFields::parse_aggregate,
+        )),
+        |(_, _, _, _, aggregate)| Part::Value(aggregate),
+      ),
+    ))(input)
+  }
+}
+
+/// Parses a list of columns introduced by its name, like `rows=[Col 1],Col2`.
+pub fn parse_columns(name: &'static str) -> impl Fn(&str) -> IResult<&str, Vec<String>> {
+  move |input| {
+    let (rest, (_, _, _, _, fields)) = tuple((
+      multispace0,
+      tag_no_case(name),
+      multispace0,
+      tag("="),
+      SelectFields::parse_select,
+    ))(input)?;
+    let mut columns = Vec::with_capacity(fields.0.len());
+    for field in fields.0 {
+      match (field.expression, field.alias) {
+        (Expression::Column(column), None) if !column.is_empty() => columns.push(column),
+        _ => {
+          return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Verify,
+          )))
+        }
+      }
+    }
+    Ok((rest, columns))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_parses_a_pivot() {
+    assert_eq!(
+      Ok(Pivot {
+        rows: vec![String::from("field 1"), String::from("field2")],
+        cols: vec![String::from("field3")],
+        value: Aggregate {
+          function: AggregateFunction::Avg,
+          field: Some(String::from("field 4")),
+        },
+      }),
+      Pivot::parse("rows=[field 1], field2 cols=field3 value=avg([field 4])")
+    );
+  }
+
+  #[test]
+  fn it_counts_rows_by_default() {
+    assert_eq!(
+      Ok(Pivot {
+        rows: vec![String::from("field1")],
+        cols: vec![String::from("field2")],
+        value: Aggregate {
+          function: AggregateFunction::Count,
+          field: None,
+        },
+      }),
+      Pivot::parse(" cols = field2  rows=field1 ")
+    );
+  }
+
+  #[test]
+  fn it_rejects_incomplete_pivots() {
+    assert!(Pivot::parse("rows=field1").is_err());
+    assert!(Pivot::parse("rows=field1 cols=count(*)").is_err());
+    assert!(Pivot::parse("rows=field1 cols=field2 value=field3").is_err());
+  }
+}
@@ -0,0 +1,216 @@
+use std::cmp::Ordering;
+
+const EXACT_LIMIT: usize = 100_000;
+const COMPRESSION: f64 = 200.0;
+const BUFFER_SIZE: usize = 2000;
+
+/// Computes quantiles exactly while the values fit in `EXACT_LIMIT`, and
+/// approximately with a t-digest, in bounded memory, beyond that.
+#[derive(Debug, Clone)]
+pub enum Quantiles {
+  Exact(Vec<f64>),
+  Approximate(TDigest),
+}
+
+impl Quantiles {
+  pub fn exact() -> Self {
+    Quantiles::Exact(Vec::new())
+  }
+
+  pub fn approximate() -> Self {
+    Quantiles::Approximate(TDigest::new())
+  }
+
+  pub fn push(&mut self, value: f64) {
+    match self {
+      Quantiles::Exact(values) if values.len() < EXACT_LIMIT => values.push(value),
+      Quantiles::Exact(values) => {
+        let mut digest = TDigest::new();
+        for &value in values.iter() {
+          digest.push(value);
+        }
+        digest.push(value);
+        *self = Quantiles::Approximate(digest);
+      }
+      Quantiles::Approximate(digest) => digest.push(value),
+    }
+  }
+
+  /// The value below which the fraction `q` of the values fall, interpolated
+  /// linearly between the closest values.
+  pub fn quantile(&mut self, q: f64) -> Option<f64> {
+    match self {
+      Quantiles::Exact(values) => {
+        if values.is_empty() {
+          return None;
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let position = q * (values.len() - 1) as f64;
+        let lower = position.floor() as usize;
+        let upper = position.ceil() as usize;
+        Some(values[lower] + (values[upper] - values[lower]) * (position - lower as f64))
+      }
+      Quantiles::Approximate(digest) => digest.quantile(q),
+    }
+  }
+}
+
+/// A merging t-digest: the values are summarized as centroids, which are
+/// smaller near the extremes so that the extreme quantiles stay accurate.
+#[derive(Debug, Clone)]
+pub struct TDigest {
+  centroids: Vec<Centroid>,
+  buffer: Vec<f64>,
+  count: f64,
+  min: f64,
+  max: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Centroid {
+  mean: f64,
+  weight: f64,
+}
+
+impl TDigest {
+  pub fn new() -> Self {
+    TDigest {
+      centroids: Vec::new(),
+      buffer: Vec::with_capacity(BUFFER_SIZE),
+      count: 0.0,
+      min: f64::INFINITY,
+      max: f64::NEG_INFINITY,
+    }
+  }
+
+  pub fn push(&mut self, value: f64) {
+    self.buffer.push(value);
+    self.count += 1.0;
+    self.min = self.min.min(value);
+    self.max = self.max.max(value);
+    if self.buffer.len() >= BUFFER_SIZE {
+      self.compress();
+    }
+  }
+
+  fn compress(&mut self) {
+    if self.buffer.is_empty() {
+      return;
+    }
+    let mut centroids: Vec<Centroid> = self
+      .buffer
+      .drain(..)
+      .map(|mean| Centroid { mean, weight: 1.0 })
+      .collect();
+    centroids.append(&mut self.centroids);
+    centroids.sort_by(|a, b| a.mean.partial_cmp(&b.mean).unwrap_or(Ordering::Equal));
+
+    let mut merged = Vec::new();
+    let mut current = centroids[0];
+    let mut weight_before = 0.0;
+    let mut weight_limit = self.count * k_inverse(k(0.0) + 1.0);
+    for centroid in centroids.into_iter().skip(1) {
+      if weight_before + current.weight + centroid.weight <= weight_limit {
+        let weight = current.weight + centroid.weight;
+        current.mean += (centroid.mean - current.mean) * centroid.weight / weight;
+        current.weight = weight;
+      } else {
+        weight_before += current.weight;
+        weight_limit = self.count * k_inverse(k(weight_before / self.count) + 1.0);
+        merged.push(current);
+        current = centroid;
+      }
+    }
+    merged.push(current);
+    self.centroids = merged;
+  }
+
+  pub fn quantile(&mut self, q: f64) -> Option<f64> {
+    self.compress();
+    let centroids = &self.centroids;
+    if centroids.is_empty() {
+      return None;
+    }
+    // Each centroid is considered to be at the middle of its weight, between
+    // the min and the max values. Like for exact quantiles, the `i`th value
+    // (starting from 0) of `n` is at the quantile `i / (n - 1)`.
+    let target = q * (self.count - 1.0) + 0.5;
+    let mut previous = (0.5, self.min);
+    let mut weight_before = 0.0;
+    for centroid in centroids {
+      let center = weight_before + centroid.weight / 2.0;
+      if target < center {
+        return Some(interpolate(previous, (center, centroid.mean), target));
+      }
+      previous = (center, centroid.mean);
+      weight_before += centroid.weight;
+    }
+    Some(interpolate(previous, (self.count - 0.5, self.max), target))
+  }
+}
+
+/// The scale function, limiting the size of the centroids depending on their
+/// quantile.
+fn k(q: f64) -> f64 {
+  COMPRESSION / (2.0 * std::f64::consts::PI) * (2.0 * q - 1.0).asin()
+}
+
+fn k_inverse(k: f64) -> f64 {
+  ((k * 2.0 * std::f64::consts::PI / COMPRESSION)
+    .min(std::f64::consts::FRAC_PI_2)
+    .sin()
+    + 1.0)
+    / 2.0
+}
+
+fn interpolate((x0, y0): (f64, f64), (x1, y1): (f64, f64), x: f64) -> f64 {
+  if x1 <= x0 {
+    y1
+  } else {
+    y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_computes_exact_quantiles() {
+    let mut quantiles = Quantiles::exact();
+    for value in &[3.0, 1.0, 4.0, 2.0] {
+      quantiles.push(*value);
+    }
+    assert_eq!(Some(1.0), quantiles.quantile(0.0));
+    assert_eq!(Some(2.5), quantiles.quantile(0.5));
+    assert_eq!(Some(3.25), quantiles.quantile(0.75));
+    assert_eq!(Some(4.0), quantiles.quantile(1.0));
+    assert_eq!(None, Quantiles::exact().quantile(0.5));
+  }
+
+  #[test]
+  fn it_approximates_quantiles() {
+    let mut quantiles = Quantiles::approximate();
+    // A shuffled sequence of 0..200_000.
+    for i in 0..200_000u64 {
+      quantiles.push((i * 7919 % 200_000) as f64);
+    }
+    for &(q, expected) in &[(0.01, 2_000.0), (0.5, 100_000.0), (0.99, 198_000.0)] {
+      let quantile = quantiles.quantile(q).unwrap();
+      assert!((quantile - expected).abs() < 200.0, "{} {}", q, quantile);
+    }
+    assert_eq!(Some(0.0), quantiles.quantile(0.0));
+    assert_eq!(Some(199_999.0), quantiles.quantile(1.0));
+  }
+
+  #[test]
+  fn it_switches_to_approximate_quantiles_for_many_values() {
+    let mut quantiles = Quantiles::exact();
+    for i in 0..=EXACT_LIMIT {
+      quantiles.push(i as f64);
+    }
+    assert!(matches!(quantiles, Quantiles::Approximate(_)));
+    let median = quantiles.quantile(0.5).unwrap();
+    assert!((median - EXACT_LIMIT as f64 / 2.0).abs() < 100.0);
+  }
+}
@@ -1,7 +1,9 @@
+use crate::select_parser::{Expression, SelectFields};
 use nom::branch::alt;
 use nom::bytes::complete::{tag, take_while, take_while1};
 use nom::character::complete::multispace0;
-use nom::combinator::all_consuming;
+use nom::combinator::{all_consuming, map};
+use nom::number::complete::recognize_float;
 use nom::sequence::tuple;
 use nom::IResult;
 
@@ -11,18 +13,39 @@ pub type ParsingError<'a> = nom::Err<nom::error::Error<&'a str>>;
 pub enum Query {
   Equality(String, String),
   Difference(String, String),
+  LessThan(String, String),
+  LessThanOrEqual(String, String),
+  GreaterThan(String, String),
+  GreaterThanOrEqual(String, String),
   OrCombination(Box<Query>, Box<Query>),
   AndCombination(Box<Query>, Box<Query>),
 }
 
 impl Query {
-  pub fn parse(query: &str) -> Result<Query, ParsingError> {
-    match all_consuming(Self::parse_query)(&query) {
+  pub fn parse(query: &str) -> Result<Query, ParsingError<'_>> {
+    match all_consuming(Self::parse_query)(query) {
       Ok((_, query)) => Ok(query),
       Err(err) => Err(err),
     }
   }
 
+  /// The fields the query refers to.
+  pub fn fields(&self) -> Vec<&String> {
+    match self {
+      Query::Equality(field, _)
+      | Query::Difference(field, _)
+      | Query::LessThan(field, _)
+      | Query::LessThanOrEqual(field, _)
+      | Query::GreaterThan(field, _)
+      | Query::GreaterThanOrEqual(field, _) => vec![field],
+      Query::OrCombination(left, right) | Query::AndCombination(left, right) => {
+        let mut fields = left.fields();
+        fields.append(&mut right.fields());
+        fields
+      }
+    }
+  }
+
   fn parse_query(input: &str) -> IResult<&str, Query> {
     return alt((
       Self::parse_or_combination,
@@ -70,38 +93,58 @@ impl Query {
   }
 
   fn parse_comparison(input: &str) -> IResult<&str, Query> {
-    let (input, (field, op, _, _, value, _, _)) = tuple((
+    let (input, (field, op, _, value, _)) = tuple((
       Self::parse_field,
-      alt((tag("="), tag("<>"))),
+      alt((
+        tag("="),
+        tag("<>"),
+        tag("<="),
+        tag(">="),
+        tag("<"),
+        tag(">"),
+      )),
       multispace0,
-      tag("'"),
-      take_while(|c| c != '\''),
-      tag("'"),
+      alt((Self::parse_quoted_value, Self::parse_number)),
       multispace0,
     ))(input)?;
-    match op {
-      "=" => Ok((
-        input,
-        Query::Equality(String::from(field), String::from(value)),
-      )),
-      _ => Ok((
-        input,
-        Query::Difference(String::from(field), String::from(value)),
-      )),
-    }
+    let value = String::from(value);
+    let query = match op {
+      "=" => Query::Equality(field, value),
+      "<>" => Query::Difference(field, value),
+      "<=" => Query::LessThanOrEqual(field, value),
+      ">=" => Query::GreaterThanOrEqual(field, value),
+      "<" => Query::LessThan(field, value),
+      _ => Query::GreaterThan(field, value),
+    };
+    Ok((input, query))
   }
 
-  fn parse_field(input: &str) -> IResult<&str, &str> {
-    return alt((
-      Self::parse_field_with_brackets,
-      Self::parse_field_without_brackets,
-    ))(input);
+  fn parse_quoted_value(input: &str) -> IResult<&str, &str> {
+    let (input, (_, value, _)) = tuple((tag("'"), take_while(|c| c != '\''), tag("'")))(input)?;
+    Ok((input, value))
+  }
+
+  fn parse_number(input: &str) -> IResult<&str, &str> {
+    recognize_float(input)
+  }
+
+  /// Aggregates like `count(*)` are named as in the select clause, so that the
+  /// having clause can refer to them.
+  fn parse_field(input: &str) -> IResult<&str, String> {
+    alt((
+      map(
+        tuple((SelectFields::parse_aggregate, multispace0)),
+        |(aggregate, _)| Expression::Aggregate(aggregate).to_string(),
+      ),
+      map(Self::parse_field_with_brackets, String::from),
+      map(Self::parse_field_without_brackets, String::from),
+    ))(input)
   }
 
   fn parse_field_without_brackets(input: &str) -> IResult<&str, &str> {
     let (input, (_, field, _)) = tuple((
       multispace0,
-      take_while1(|c| c != ' ' && c != '='),
+      take_while1(|c| c != ' ' && c != '=' && c != '<' && c != '>'),
       multispace0,
     ))(input)?;
     Ok((input, field))
@@ -342,4 +385,34 @@ mod tests {
       Query::parse("[my field] = 'my_value' and ([my field] = 'other value' or [my other field] = 'another value') and [last field] = 'v' or [last field] = 'last value'")
     );
   }
+
+  #[test]
+  fn ordering_comparisons_with_numbers() {
+    assert_eq!(
+      Ok(Query::AndCombination(
+        Box::new(Query::GreaterThan(
+          String::from("field1"),
+          String::from("50")
+        )),
+        Box::new(Query::LessThanOrEqual(
+          String::from("field 2"),
+          String::from("-1.5")
+        ))
+      )),
+      Query::parse("field1>50 and [field 2] <= -1.5")
+    );
+  }
+
+  #[test]
+  fn invalid_numbers() {
+    assert!(Query::parse("field1 > -").is_err());
+    assert!(Query::parse("field1 > .").is_err());
+    assert!(Query::parse("field1 = 1-2-3").is_err());
+  }
+
+  #[test]
+  fn comparison_on_aggregates() {
+    let query = Query::parse("COUNT(*) > 50 or avg([field 1]) >= '2'").unwrap();
+    assert_eq!(vec!["count(*)", "avg(field 1)"], query.fields());
+  }
 }
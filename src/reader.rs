@@ -1,138 +1,754 @@
+use crate::aggregate::Accumulator;
+use crate::args::DEFAULT_MAX_PIVOT_COLUMNS;
+use crate::dataset::{Dataset, Records, SharedBadRows};
+use crate::describe::{ColumnStats, DESCRIBE_HEADERS};
+use crate::distinct::Distinct;
 use crate::errors::AppError;
+use crate::histogram::{self, BUCKET_HEADERS};
+use crate::input::{self, Source};
+use crate::join::{self, JoinType};
+use crate::output::{self, OutputFormat, Renderer, Window};
+use crate::pivot_parser::Pivot;
 use crate::query_parser::Query;
+use crate::select_parser::{Expression, SelectField, SelectFields};
+use crate::sort::{compare_values, Sorter};
+use crate::types::parse_float;
 use crate::Args;
-use csv::StringRecordsIter;
+use csv::StringRecord;
 use num_format::{Locale, ToFormattedString};
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::error::Error;
 use std::io::Write;
-use tabular::{Row, Table};
+use std::path::PathBuf;
+
+const OTHER_VALUES: &str = "(other)";
 
 pub fn run(args: &Args, writer: &mut impl Write) -> Result<(), Box<dyn Error>> {
-  let mut reader = csv::ReaderBuilder::new()
-    .has_headers(true)
-    .from_path(&args.filename)?;
+  match &args.output_file {
+    Some(output_file) => output::write_atomically(output_file, |file| run_to_writer(args, file)),
+    None => run_to_writer(args, writer),
+  }
+}
 
-  let headers = reader.headers()?;
-  let header_positions = header_positions(&headers);
+fn run_to_writer(args: &Args, writer: &mut impl Write) -> Result<(), Box<dyn Error>> {
+  let (dataset, all_bad_rows) = open_dataset(args)?;
+  run_on_dataset(dataset, args, writer)?;
+  for bad_rows in all_bad_rows {
+    if bad_rows.borrow().count > 0 {
+      eprint!("{}", bad_rows.borrow());
+    }
+  }
+  Ok(())
+}
+
+/// Reads the input files, joined with or filtered by the other files if
+/// needed. Also returns the rows skipped in each of the files read, which are
+/// only known once the rows have been read.
+fn open_dataset(args: &Args) -> Result<(Dataset, Vec<SharedBadRows>), Box<dyn Error>> {
+  let sources = input::sources(&args.filenames, args.compression, args.parse_encoding()?)?;
+  let mut dataset = Dataset::concat(sources, args)?;
+  let mut all_bad_rows = vec![dataset.bad_rows.clone()];
+  if let (Some(filename), Some(condition)) = (&args.join, args.parse_on()?) {
+    let other = open_other(filename, args)?;
+    all_bad_rows.push(other.bad_rows.clone());
+    // The rows of the smallest file are loaded in memory.
+    let build_left = match input::total_size(&args.filenames) {
+      Some(size) => std::fs::metadata(filename).is_ok_and(|other| size < other.len()),
+      None => false,
+    };
+    dataset = join::join(
+      dataset,
+      other,
+      &condition,
+      args.join_type.unwrap_or(JoinType::Inner),
+      build_left,
+    )?;
+  }
+  for (filter, keep) in [
+    (args.parse_keep_if_in()?, true),
+    (args.parse_drop_if_in()?, false),
+  ] {
+    if let Some(filter) = filter {
+      let other = open_other(&filter.filename, args)?;
+      all_bad_rows.push(other.bad_rows.clone());
+      dataset = join::semi_join(dataset, other, &filter, keep)?;
+    }
+  }
+  if let Some(melt) = args.parse_melt()? {
+    dataset = dataset.melt(&melt)?;
+  }
+  Ok((dataset, all_bad_rows))
+}
 
-  let headers_to_display: Vec<String> = match args.parse_select()? {
-    Some(headers) => headers.0,
-    None => reader.headers()?.into_iter().map(String::from).collect(),
+/// Reads another file, e.g. for `--join`. Its compression and encoding are
+/// detected on their own, as `--compression` and `--encoding` are about the
+/// main input, and it never gets a `_file` column. Malformed rows are handled
+/// like in the main input.
+fn open_other(filename: &str, args: &Args) -> Result<Dataset, Box<dyn Error>> {
+  let source = Source::file(filename, None, None);
+  let options = Args {
+    flexible: args.flexible,
+    skip_bad_rows: args.skip_bad_rows,
+    ..Default::default()
   };
+  Dataset::concat(vec![source], &options)
+}
+
+fn run_on_dataset(
+  dataset: Dataset,
+  args: &Args,
+  writer: &mut impl Write,
+) -> Result<(), Box<dyn Error>> {
+  if args.describe {
+    let columns = match args.parse_select()? {
+      Some(select) if !select.has_aggregates() => select
+        .0
+        .iter()
+        .map(|field| field.expression.to_string())
+        .collect(),
+      Some(_) => {
+        return Err(Box::new(AppError::SelectParsingError(
+          args.select.clone().unwrap_or_default(),
+        )))
+      }
+      None => dataset.headers.clone(),
+    };
+    let mut renderer = output::renderer(args, writer)?;
+    return display_describe(dataset, &args.parse_query()?, &columns, renderer.as_mut());
+  }
+
+  if let Some(column) = args.parse_histogram()? {
+    let values = numeric_values(dataset, &args.parse_query()?, &column)?;
+    let buckets = histogram::buckets(&values, args.parse_bins()?);
+    if args.output_format() == OutputFormat::Table {
+      histogram::write_chart(&buckets, writer)?;
+      return Ok(());
+    }
+    let mut renderer = output::renderer(args, writer)?;
+    let headers: Vec<String> = BUCKET_HEADERS
+      .iter()
+      .map(|&header| String::from(header))
+      .collect();
+    renderer.write_headers(&headers)?;
+    for bucket in buckets {
+      renderer.write_row(&bucket.row())?;
+      if renderer.is_done() {
+        break;
+      }
+    }
+    return renderer.finish();
+  }
+
+  if let Some(column) = args.parse_frequency()? {
+    let mut renderer = output::renderer(args, writer)?;
+    return display_frequency(
+      dataset,
+      &args.parse_query()?,
+      &column,
+      args.top,
+      renderer.as_mut(),
+    );
+  }
 
-  let query = args.parse_query()?;
+  let select = args.parse_select()?;
+  let group_by = args.parse_group_by()?;
+  let having = args.parse_having()?;
+  let (dataset, query, headers_to_display, header_names) =
+    if let Some(pivot) = args.parse_pivot()? {
+      let max_columns = args.max_pivot_columns.unwrap_or(DEFAULT_MAX_PIVOT_COLUMNS);
+      let dataset = pivot_dataset(dataset, &pivot, &args.parse_query()?, max_columns)?;
+      let headers = dataset.headers.clone();
+      (dataset, None, headers.clone(), headers)
+    } else if group_by.is_some()
+      || having.is_some()
+      || select.as_ref().is_some_and(SelectFields::has_aggregates)
+    {
+      let group_by = group_by.unwrap_or_default();
+      let mut select = match select {
+        Some(select) => select.0,
+        None => group_by
+          .iter()
+          .map(|column| SelectField::new(Expression::Column(column.clone())))
+          .collect(),
+      };
+      let selected = select.len();
+      if let Some(having) = &having {
+        add_having_fields(&mut select, having, &group_by, &dataset.headers)?;
+      }
+      let dataset = aggregate(dataset, &select, &group_by, &args.parse_query()?)?;
+      let headers_to_display = dataset.headers[..selected].to_vec();
+      let header_names = headers_to_display.clone();
+      (dataset, having, headers_to_display, header_names)
+    } else {
+      let (headers_to_display, header_names) = match select {
+        Some(select) => (
+          select
+            .0
+            .iter()
+            .map(|field| field.expression.to_string())
+            .collect(),
+          select.0.iter().map(ToString::to_string).collect(),
+        ),
+        None => (dataset.headers.clone(), dataset.headers.clone()),
+      };
+      (
+        dataset,
+        args.parse_query()?,
+        headers_to_display,
+        header_names,
+      )
+    };
+  let header_positions = header_positions(&dataset.headers);
+
+  let temp_dir = args.temp_dir.as_ref().map(PathBuf::from);
+  let distinct = if args.distinct {
+    Some(Distinct::new(args.parse_memory_limit()?, temp_dir.clone()))
+  } else {
+    None
+  };
+  let sorter = match args.parse_order_by()? {
+    Some(order_by) => {
+      // Distinct rows are sorted once selected, so on the selected columns.
+      let sort_positions = if distinct.is_some() {
+        let mut row_positions = self::header_positions(&headers_to_display);
+        row_positions.extend(self::header_positions(&header_names));
+        row_positions
+      } else {
+        // Aliases given in --select refer to their column.
+        let mut positions = header_positions.clone();
+        for (name, column) in header_names.iter().zip(&headers_to_display) {
+          if let Some(&position) = header_positions.get(column) {
+            positions.insert(name.clone(), position);
+          }
+        }
+        positions
+      };
+      Some(
+        Sorter::new(order_by, &sort_positions, args.window().top())?
+          .with_memory_limit(args.parse_memory_limit()?, temp_dir),
+      )
+    }
+    None => None,
+  };
 
   if args.count {
-    display_count(reader.records(), &query, &header_positions, writer)?;
+    display_count(
+      dataset.records,
+      &query,
+      &header_positions,
+      &headers_to_display,
+      distinct,
+      &args.window(),
+      writer,
+    )?;
   } else {
+    let mut renderer = output::renderer(args, writer)?;
+    renderer.write_headers(&header_names)?;
     display_table(
-      reader.records(),
+      dataset.records,
       &query,
       &header_positions,
       &headers_to_display,
-      writer,
+      distinct,
+      sorter,
+      renderer.as_mut(),
     )?;
   }
 
   Ok(())
 }
 
-fn display_count<R: std::io::Read>(
-  records: StringRecordsIter<R>,
+/// Adds the fields the having clause refers to and that are not selected, so
+/// that they are computed too. They can be aggregates, or group columns.
+fn add_having_fields(
+  select: &mut Vec<SelectField>,
+  having: &Query,
+  group_by: &[String],
+  headers: &[String],
+) -> Result<(), AppError> {
+  for field in having.fields() {
+    if select.iter().any(|selected| &selected.to_string() == field) {
+      continue;
+    }
+    let expression = match SelectFields::parse(field) {
+      Ok(SelectFields(fields)) if fields.len() == 1 && fields[0].alias.is_none() => {
+        fields[0].expression.clone()
+      }
+      _ => Expression::Column(field.clone()),
+    };
+    let is_valid = match &expression {
+      Expression::Column(column) => group_by.contains(column),
+      Expression::Aggregate(aggregate) => aggregate
+        .field
+        .as_ref()
+        .is_none_or(|field| headers.contains(field)),
+    };
+    if !is_valid {
+      return Err(AppError::InvalidFieldInHavingClause(field.clone()));
+    }
+    select.push(SelectField::new(expression));
+  }
+  Ok(())
+}
+
+/// Groups the rows matching the query on the `group_by` columns and computes
+/// the selected aggregates for each group, as a new dataset whose columns are
+/// the selected fields. Groups keep the order in which they first appear.
+/// Without `group_by`, all the rows form a single group.
+fn aggregate(
+  dataset: Dataset,
+  select: &[SelectField],
+  group_by: &[String],
+  query: &Option<Query>,
+) -> Result<Dataset, Box<dyn Error>> {
+  let header_positions = header_positions(&dataset.headers);
+  let mut group_positions = Vec::with_capacity(group_by.len());
+  for field in group_by {
+    match header_positions.get(field) {
+      Some(&position) => group_positions.push(position),
+      None => {
+        return Err(Box::new(AppError::InvalidFieldInGroupByClause(
+          field.clone(),
+        )))
+      }
+    }
+  }
+
+  // Each selected field is either one of the group columns, or an aggregate.
+  let mut outputs = Vec::with_capacity(select.len());
+  let mut aggregates = Vec::new();
+  for field in select {
+    match &field.expression {
+      Expression::Column(column) => match group_by.iter().position(|group| group == column) {
+        Some(i) => outputs.push(Err(i)),
+        None if header_positions.contains_key(column) => {
+          return Err(Box::new(AppError::FieldNotGrouped(column.clone())))
+        }
+        None => {
+          return Err(Box::new(AppError::InvalidFieldInSelectClause(
+            column.clone(),
+          )))
+        }
+      },
+      Expression::Aggregate(aggregate) => {
+        let position = match &aggregate.field {
+          Some(field) => match header_positions.get(field) {
+            Some(&position) => Some(position),
+            None => {
+              return Err(Box::new(AppError::InvalidFieldInSelectClause(
+                field.clone(),
+              )))
+            }
+          },
+          None => None,
+        };
+        outputs.push(Ok(aggregates.len()));
+        aggregates.push((aggregate.function, position));
+      }
+    }
+  }
+  let new_accumulators = || -> Vec<Accumulator> {
+    aggregates
+      .iter()
+      .map(|&(function, _)| Accumulator::new(function))
+      .collect()
+  };
+
+  let mut group_indices: HashMap<Vec<String>, usize> = HashMap::new();
+  let mut groups: Vec<(Vec<String>, Vec<Accumulator>)> = Vec::new();
+  if group_by.is_empty() {
+    group_indices.insert(Vec::new(), 0);
+    groups.push((Vec::new(), new_accumulators()));
+  }
+  for record in dataset.records {
+    let record = record?;
+    if !should_display_record(&record, query, &header_positions)? {
+      continue;
+    }
+    let key: Vec<String> = group_positions
+      .iter()
+      .map(|&position| String::from(record.get(position).unwrap_or("")))
+      .collect();
+    let index = match group_indices.get(&key) {
+      Some(&index) => index,
+      None => {
+        group_indices.insert(key.clone(), groups.len());
+        groups.push((key, new_accumulators()));
+        groups.len() - 1
+      }
+    };
+    let accumulators = &mut groups[index].1;
+    for (accumulator, &(_, position)) in accumulators.iter_mut().zip(&aggregates) {
+      accumulator.push(position.map(|position| record.get(position).unwrap_or("")));
+    }
+  }
+
+  let rows = groups.into_iter().map(move |(key, mut accumulators)| {
+    let row: Vec<String> = outputs
+      .iter()
+      .map(|output| match output {
+        Err(i) => key[*i].clone(),
+        Ok(i) => accumulators[*i].result(),
+      })
+      .collect();
+    Ok(StringRecord::from(row))
+  });
+  Ok(Dataset {
+    headers: select.iter().map(ToString::to_string).collect(),
+    records: Box::new(rows),
+    bad_rows: dataset.bad_rows,
+  })
+}
+
+/// Aggregates the rows matching the query by the pivot rows and columns, and
+/// reshapes the result with a column for each distinct value of the pivot
+/// columns, in ascending order. When there are several pivot columns, their
+/// values are joined with `/`.
+fn pivot_dataset(
+  dataset: Dataset,
+  pivot: &Pivot,
+  query: &Option<Query>,
+  max_columns: usize,
+) -> Result<Dataset, Box<dyn Error>> {
+  let group_by: Vec<String> = pivot.rows.iter().chain(&pivot.cols).cloned().collect();
+  let mut select: Vec<SelectField> = group_by
+    .iter()
+    .map(|column| SelectField::new(Expression::Column(column.clone())))
+    .collect();
+  select.push(SelectField::new(Expression::Aggregate(pivot.value.clone())));
+  let aggregated = aggregate(dataset, &select, &group_by, query)?;
+
+  let rows_len = pivot.rows.len();
+  let mut row_indices: HashMap<Vec<String>, usize> = HashMap::new();
+  let mut row_keys: Vec<Vec<String>> = Vec::new();
+  let mut col_indices: HashMap<Vec<String>, usize> = HashMap::new();
+  let mut col_keys: Vec<Vec<String>> = Vec::new();
+  let mut cells: HashMap<(usize, usize), String> = HashMap::new();
+  for record in aggregated.records {
+    let record = record?;
+    let row_key: Vec<String> = record.iter().take(rows_len).map(String::from).collect();
+    let col_key: Vec<String> = record
+      .iter()
+      .skip(rows_len)
+      .take(pivot.cols.len())
+      .map(String::from)
+      .collect();
+    let row_index = *row_indices.entry(row_key.clone()).or_insert_with(|| {
+      row_keys.push(row_key);
+      row_keys.len() - 1
+    });
+    let col_index = *col_indices.entry(col_key.clone()).or_insert_with(|| {
+      col_keys.push(col_key);
+      col_keys.len() - 1
+    });
+    if col_keys.len() > max_columns {
+      return Err(Box::new(AppError::TooManyPivotColumns(max_columns)));
+    }
+    cells.insert(
+      (row_index, col_index),
+      String::from(record.get(group_by.len()).unwrap_or("")),
+    );
+  }
+
+  let mut col_order: Vec<usize> = (0..col_keys.len()).collect();
+  col_order.sort_by(|&a, &b| {
+    col_keys[a]
+      .iter()
+      .zip(&col_keys[b])
+      .map(|(a, b)| compare_values(a, b))
+      .find(|&ordering| ordering != Ordering::Equal)
+      .unwrap_or(Ordering::Equal)
+  });
+  let mut headers = pivot.rows.clone();
+  headers.extend(col_order.iter().map(|&col| col_keys[col].join("/")));
+  let rows = row_keys
+    .into_iter()
+    .enumerate()
+    .map(move |(row_index, mut row)| {
+      for col in &col_order {
+        row.push(cells.remove(&(row_index, *col)).unwrap_or_default());
+      }
+      Ok(StringRecord::from(row))
+    });
+  Ok(Dataset {
+    headers,
+    records: Box::new(rows),
+    bad_rows: aggregated.bad_rows,
+  })
+}
+
+fn display_count(
+  records: Records,
   query: &Option<Query>,
   header_positions: &HashMap<String, usize>,
+  headers_to_display: &[String],
+  mut distinct: Option<Distinct>,
+  window: &Window,
   writer: &mut impl Write,
 ) -> Result<(), Box<dyn Error>> {
   let mut total = 0;
   let mut filtered = 0;
+  let mut complete = true;
   for record in records {
     let record = record?;
-    if should_display_record(&record, &query, &header_positions)? {
+    total += 1;
+    if !should_display_record(&record, query, header_positions)? {
+      continue;
+    }
+    if let Some(distinct) = &mut distinct {
+      let row = row_for_record(&record, header_positions, headers_to_display)?;
+      if distinct.push(row)?.is_none() {
+        continue;
+      }
+    }
+    filtered += 1;
+    if window.is_full(filtered) {
+      complete = false;
+      break;
+    }
+  }
+  if let (Some(distinct), true) = (distinct, complete) {
+    for row in distinct.finish()? {
+      row?;
       filtered += 1;
+      if window.is_full(filtered) {
+        break;
+      }
     }
-    total += 1;
   }
-  if query.is_some() {
+  let count = window.count(filtered);
+  if query.is_some() && complete {
     writeln!(
       writer,
       "{} {} ({} total)",
-      filtered.to_formatted_string(&Locale::en),
-      if filtered > 1 { "rows" } else { "row" },
+      count.to_formatted_string(&Locale::en),
+      if count > 1 { "rows" } else { "row" },
       total.to_formatted_string(&Locale::en)
     )?;
   } else {
     writeln!(
       writer,
       "{} {}",
-      total.to_formatted_string(&Locale::en),
-      if total > 1 { "rows" } else { "row" },
+      count.to_formatted_string(&Locale::en),
+      if count > 1 { "rows" } else { "row" },
     )?;
   }
   Ok(())
 }
 
-fn display_table<R: std::io::Read>(
-  records: StringRecordsIter<R>,
+/// Displays statistics about the values of the columns, among the rows
+/// matching the query, with one row per column.
+fn display_describe(
+  dataset: Dataset,
   query: &Option<Query>,
-  header_positions: &HashMap<String, usize>,
-  headers_to_display: &Vec<String>,
-  writer: &mut impl Write,
+  columns: &[String],
+  renderer: &mut dyn Renderer,
 ) -> Result<(), Box<dyn Error>> {
-  let mut table = Table::new(&row_spec(&headers_to_display));
-  table.add_row(headers_row(&headers_to_display));
-  for record in records {
+  let header_positions = header_positions(&dataset.headers);
+  let mut positions = Vec::with_capacity(columns.len());
+  for column in columns {
+    match header_positions.get(column) {
+      Some(&position) => positions.push(position),
+      None => {
+        return Err(Box::new(AppError::InvalidFieldInSelectClause(
+          column.clone(),
+        )))
+      }
+    }
+  }
+  let mut stats: Vec<ColumnStats> = columns
+    .iter()
+    .map(|column| ColumnStats::new(column))
+    .collect();
+  for record in dataset.records {
     let record = record?;
-    if !should_display_record(&record, &query, &header_positions)? {
+    if !should_display_record(&record, query, &header_positions)? {
       continue;
     }
-    table.add_row(row_for_record(
-      &record,
-      &header_positions,
-      &headers_to_display,
-    )?);
+    for (column_stats, &position) in stats.iter_mut().zip(&positions) {
+      column_stats.push(record.get(position).unwrap_or(""));
+    }
   }
-  write!(writer, "{}", table)?;
-  Ok(())
+
+  let headers: Vec<String> = DESCRIBE_HEADERS
+    .iter()
+    .map(|&header| String::from(header))
+    .collect();
+  renderer.write_headers(&headers)?;
+  for column_stats in stats {
+    renderer.write_row(&column_stats.row())?;
+    if renderer.is_done() {
+      break;
+    }
+  }
+  renderer.finish()
 }
 
-fn header_positions(headers: &csv::StringRecord) -> HashMap<String, usize> {
-  let mut header_positions: HashMap<String, usize> = HashMap::new();
-  for (i, header) in headers.iter().enumerate() {
-    header_positions.insert(String::from(header), i);
+/// The values of the column that are numbers, among the rows matching the
+/// query.
+fn numeric_values(
+  dataset: Dataset,
+  query: &Option<Query>,
+  column: &str,
+) -> Result<Vec<f64>, Box<dyn Error>> {
+  let header_positions = header_positions(&dataset.headers);
+  let position = match header_positions.get(column) {
+    Some(&position) => position,
+    None => {
+      return Err(Box::new(AppError::InvalidFieldInHistogram(String::from(
+        column,
+      ))))
+    }
+  };
+  let mut values = Vec::new();
+  for record in dataset.records {
+    let record = record?;
+    if should_display_record(&record, query, &header_positions)? {
+      values.extend(parse_float(record.get(position).unwrap_or("")));
+    }
   }
-  header_positions
+  Ok(values)
 }
 
-fn row_spec(headers: &Vec<String>) -> String {
-  headers
-    .into_iter()
-    .map(|_| String::from("{:<}"))
-    .collect::<Vec<String>>()
-    .join(" ")
+/// Displays the distinct values of the column with their number of occurrences
+/// and percentage among the rows matching the query, the most frequent first.
+/// Beyond the `top` values, the other ones are counted together.
+fn display_frequency(
+  dataset: Dataset,
+  query: &Option<Query>,
+  column: &str,
+  top: Option<usize>,
+  renderer: &mut dyn Renderer,
+) -> Result<(), Box<dyn Error>> {
+  let header_positions = header_positions(&dataset.headers);
+  let position = match header_positions.get(column) {
+    Some(&position) => position,
+    None => {
+      return Err(Box::new(AppError::InvalidFieldInFrequency(String::from(
+        column,
+      ))))
+    }
+  };
+  let mut value_indices: HashMap<String, usize> = HashMap::new();
+  let mut frequencies: Vec<(String, usize)> = Vec::new();
+  let mut filtered = 0;
+  for record in dataset.records {
+    let record = record?;
+    if !should_display_record(&record, query, &header_positions)? {
+      continue;
+    }
+    filtered += 1;
+    let value = record.get(position).unwrap_or("");
+    match value_indices.get(value) {
+      Some(&index) => frequencies[index].1 += 1,
+      None => {
+        value_indices.insert(String::from(value), frequencies.len());
+        frequencies.push((String::from(value), 1));
+      }
+    }
+  }
+
+  frequencies.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+  if let Some(top) = top {
+    if frequencies.len() > top {
+      let other = frequencies.drain(top..).map(|(_, count)| count).sum();
+      frequencies.push((String::from(OTHER_VALUES), other));
+    }
+  }
+
+  renderer.write_headers(&[
+    String::from(column),
+    String::from("count"),
+    String::from("percentage"),
+  ])?;
+  for (value, count) in frequencies {
+    let percentage = 100.0 * count as f64 / filtered as f64;
+    renderer.write_row(&[value, count.to_string(), format!("{:.2}", percentage)])?;
+    if renderer.is_done() {
+      break;
+    }
+  }
+  renderer.finish()
+}
+
+fn display_table(
+  records: Records,
+  query: &Option<Query>,
+  header_positions: &HashMap<String, usize>,
+  headers_to_display: &[String],
+  mut distinct: Option<Distinct>,
+  mut sorter: Option<Sorter>,
+  renderer: &mut dyn Renderer,
+) -> Result<(), Box<dyn Error>> {
+  for record in records {
+    let record = record?;
+    if !should_display_record(&record, query, header_positions)? {
+      continue;
+    }
+    let row = row_for_record(&record, header_positions, headers_to_display)?;
+    let row = match &mut distinct {
+      Some(distinct) => match distinct.push(row)? {
+        Some(row) => row,
+        None => continue,
+      },
+      None => row,
+    };
+    match &mut sorter {
+      Some(sorter) if distinct.is_some() => sorter.push(&StringRecord::from(row.clone()), row)?,
+      Some(sorter) => sorter.push(&record, row)?,
+      None => {
+        renderer.write_row(&row)?;
+        if renderer.is_done() {
+          break;
+        }
+      }
+    }
+  }
+  if let Some(distinct) = distinct {
+    for row in distinct.finish()? {
+      if renderer.is_done() {
+        break;
+      }
+      let row = row?;
+      match &mut sorter {
+        Some(sorter) => sorter.push(&StringRecord::from(row.clone()), row)?,
+        None => renderer.write_row(&row)?,
+      }
+    }
+  }
+  if let Some(sorter) = sorter {
+    for row in sorter.finish()? {
+      renderer.write_row(&row?)?;
+      if renderer.is_done() {
+        break;
+      }
+    }
+  }
+  renderer.finish()
 }
 
-fn headers_row(headers: &Vec<String>) -> Row {
-  let mut headers_row = Row::new();
-  for header in headers {
-    headers_row.add_cell(&header);
+fn header_positions(headers: &[String]) -> HashMap<String, usize> {
+  let mut header_positions: HashMap<String, usize> = HashMap::new();
+  for (i, header) in headers.iter().enumerate() {
+    header_positions.insert(header.clone(), i);
   }
-  return headers_row;
+  header_positions
 }
 
 fn row_for_record(
   record: &csv::StringRecord,
   header_positions: &HashMap<String, usize>,
-  headers_to_display: &Vec<String>,
-) -> Result<Row, AppError> {
-  let mut row = Row::new();
+  headers_to_display: &[String],
+) -> Result<Vec<String>, AppError> {
+  let mut row = Vec::with_capacity(headers_to_display.len());
   for header in headers_to_display {
     match header_positions.get(header) {
-      Some(&header_pos) => row.add_cell(record.get(header_pos).unwrap()),
+      Some(&header_pos) => row.push(String::from(record.get(header_pos).unwrap_or(""))),
       None => return Err(AppError::InvalidFieldInSelectClause(header.clone())),
     };
   }
-  return Ok(row);
+  Ok(row)
 }
 
 fn should_display_record(
@@ -142,28 +758,59 @@ fn should_display_record(
 ) -> Result<bool, AppError> {
   match query {
     Some(Query::Equality(field, value)) => match header_positions.get(field) {
-      Some(&col_pos) => return Ok(record.get(col_pos).unwrap() == value),
-      None => return Err(AppError::InvalidFieldInWhereClause(field.clone())),
+      Some(&col_pos) => Ok(record.get(col_pos).unwrap_or("") == value),
+      None => Err(AppError::InvalidFieldInWhereClause(field.clone())),
     },
     Some(Query::Difference(field, value)) => match header_positions.get(field) {
-      Some(&col_pos) => return Ok(record.get(col_pos).unwrap() != value),
-      None => return Err(AppError::InvalidFieldInWhereClause(field.clone())),
+      Some(&col_pos) => Ok(record.get(col_pos).unwrap_or("") != value),
+      None => Err(AppError::InvalidFieldInWhereClause(field.clone())),
     },
+    Some(Query::LessThan(field, value)) => compare_field(record, field, value, header_positions)
+      .map(|ordering| ordering == Some(Ordering::Less)),
+    Some(Query::LessThanOrEqual(field, value)) => {
+      compare_field(record, field, value, header_positions)
+        .map(|ordering| matches!(ordering, Some(Ordering::Less | Ordering::Equal)))
+    }
+    Some(Query::GreaterThan(field, value)) => compare_field(record, field, value, header_positions)
+      .map(|ordering| ordering == Some(Ordering::Greater)),
+    Some(Query::GreaterThanOrEqual(field, value)) => {
+      compare_field(record, field, value, header_positions)
+        .map(|ordering| matches!(ordering, Some(Ordering::Greater | Ordering::Equal)))
+    }
     Some(Query::OrCombination(left, right)) => Ok(
-      should_display_record(&record, &Some(*left.clone()), &header_positions)?
-        || should_display_record(&record, &Some(*right.clone()), &header_positions)?,
+      should_display_record(record, &Some(*left.clone()), header_positions)?
+        || should_display_record(record, &Some(*right.clone()), header_positions)?,
     ),
     Some(Query::AndCombination(left, right)) => Ok(
-      should_display_record(&record, &Some(*left.clone()), &header_positions)?
-        && should_display_record(&record, &Some(*right.clone()), &header_positions)?,
+      should_display_record(record, &Some(*left.clone()), header_positions)?
+        && should_display_record(record, &Some(*right.clone()), header_positions)?,
     ),
     _ => Ok(true),
   }
 }
 
+/// Compares the value of the field with the given one, numerically if both are
+/// numbers. Empty values are not comparable.
+fn compare_field(
+  record: &csv::StringRecord,
+  field: &str,
+  value: &str,
+  header_positions: &HashMap<String, usize>,
+) -> Result<Option<Ordering>, AppError> {
+  match header_positions.get(field) {
+    Some(&col_pos) => match record.get(col_pos).unwrap_or("") {
+      "" => Ok(None),
+      field_value => Ok(Some(compare_values(field_value, value))),
+    },
+    None => Err(AppError::InvalidFieldInWhereClause(String::from(field))),
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::input::Source;
+  use clap::Clap;
 
   fn get_output(
     select: Option<String>,
@@ -171,10 +818,11 @@ mod tests {
     count: bool,
   ) -> Result<String, Box<dyn Error>> {
     let args = Args {
-      filename: String::from("example_data/weather.csv"),
+      filenames: vec![String::from("example_data/weather.csv")],
       select,
       where_,
       count,
+      ..Default::default()
     };
     let mut out = Vec::new();
     run(&args, &mut out)?;
@@ -182,6 +830,14 @@ mod tests {
     Ok(String::from(String::from_utf8(out)?.trim()))
   }
 
+  fn get_output_for_args(args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let args = Args::try_parse_from(std::iter::once("csvread").chain(args.iter().copied()))?;
+    let mut out = Vec::new();
+    run(&args, &mut out)?;
+
+    Ok(String::from(String::from_utf8(out)?.trim()))
+  }
+
   #[test]
   fn without_filter() -> Result<(), Box<dyn Error>> {
     let out = get_output(None, None, false)?;
@@ -321,4 +977,772 @@ Data.Precipitation Date.Full  Date.Month Date.Week of Date.Year Station.City Sta
     assert_eq!("1 row (16,743 total)", out);
     Ok(())
   }
+
+  #[test]
+  fn from_input_stream() -> Result<(), Box<dyn Error>> {
+    let args = Args {
+      where_: Some(String::from("city = 'Paris'")),
+      ..Default::default()
+    };
+    let source = Source::from_reader(
+      "-",
+      Box::new("city,country\nParis,France\nLondon,UK\n".as_bytes()),
+    );
+    let mut out = Vec::new();
+    run_on_dataset(Dataset::concat(vec![source], &args)?, &args, &mut out)?;
+    assert_eq!(
+      "
+city  country
+Paris France
+      "
+      .trim(),
+      String::from_utf8(out)?.trim()
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_several_files() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&["example_data/weather.csv", "example_data/*.csv", "--count"])?;
+    assert_eq!("33,486 rows", out);
+    Ok(())
+  }
+
+  #[test]
+  fn with_file_column() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--file-column",
+      "--select",
+      "Station.City, _file",
+      "--where",
+      "[Data.Temperature.Avg Temp] = '-21' and _file = 'example_data/weather.csv'",
+    ])?;
+    assert_eq!(
+      "
+Station.City _file
+Fairbanks    example_data/weather.csv
+Northway     example_data/weather.csv
+      "
+      .trim(),
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_csv_output() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--select",
+      "Date.Full, Station.Location",
+      "--where",
+      "[Data.Temperature.Avg Temp] = '-21'",
+      "--output",
+      "csv",
+    ])?;
+    assert_eq!(
+      "
+Date.Full,Station.Location
+2016-12-11,\"Fairbanks, AK\"
+2016-12-18,\"Northway, AK\"
+      "
+      .trim(),
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_limit_and_offset() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--select",
+      "Date.Full, Station.City",
+      "--offset",
+      "1",
+      "--limit",
+      "2",
+    ])?;
+    assert_eq!(
+      "
+Date.Full  Station.City
+2016-01-03 Huntsville
+2016-01-03 Mobile
+      "
+      .trim(),
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_tail() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--select",
+      "Date.Full, Station.City",
+      "--where",
+      "[Data.Temperature.Avg Temp] = '-21'",
+      "--tail",
+      "1",
+    ])?;
+    assert_eq!(
+      "
+Date.Full  Station.City
+2016-12-18 Northway
+      "
+      .trim(),
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_count_and_limit() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&["example_data/weather.csv", "--count", "--limit", "10"])?;
+    assert_eq!("10 rows", out);
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--count",
+      "--where",
+      "[Data.Temperature.Avg Temp] = '-21'",
+      "--offset",
+      "1",
+      "--limit",
+      "10",
+    ])?;
+    assert_eq!("1 row (16,743 total)", out);
+    Ok(())
+  }
+
+  #[test]
+  fn with_order_by_and_limit() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--select",
+      "Date.Full, Station.City, [Data.Temperature.Avg Temp]",
+      "--order-by",
+      "[Data.Temperature.Avg Temp], Station.City desc",
+      "--limit",
+      "4",
+    ])?;
+    assert_eq!(
+      "
+Date.Full  Station.City Data.Temperature.Avg Temp
+2016-12-04 Bettles      -27
+2016-12-18 Northway     -21
+2016-12-11 Fairbanks    -21
+2016-12-11 Gulkana      -20
+      "
+      .trim(),
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_order_by_spilling_to_disk() -> Result<(), Box<dyn Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let args = [
+      "example_data/weather.csv",
+      "--select",
+      "Date.Full, Station.City, [Data.Temperature.Avg Temp]",
+      "--order-by",
+      "[Data.Temperature.Avg Temp] desc, Station.City",
+      "--output",
+      "csv",
+    ];
+    let in_memory = get_output_for_args(&args)?;
+    let temp_dir_arg = temp_dir.path().to_str().unwrap();
+    let spilled = get_output_for_args(
+      &[
+        &args[..],
+        &["--memory-limit", "4K", "--temp-dir", temp_dir_arg],
+      ]
+      .concat(),
+    )?;
+    assert_eq!(in_memory, spilled);
+    assert_eq!(0, std::fs::read_dir(temp_dir.path())?.count());
+    Ok(())
+  }
+
+  #[test]
+  fn with_invalid_memory_limit() {
+    let result = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--order-by",
+      "Date.Full",
+      "--memory-limit",
+      "lots",
+    ]);
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn with_group_by() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--select",
+      "[Station.State], count(*), avg([Data.Temperature.Avg Temp]), max([Data.Wind.Speed])",
+      "--group-by",
+      "[Station.State]",
+      "--where",
+      "[Station.State] = 'Alaska' or [Station.State] = 'Texas'",
+    ])?;
+    assert_eq!(
+      "
+Station.State count(*) avg(Data.Temperature.Avg Temp) max(Data.Wind.Speed)
+Alaska        1719     41.1576497964                  27.58
+Texas         1272     68.1816037736                  20.3
+      "
+      .trim(),
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_aggregates_and_no_group_by() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--select",
+      "count(*), count(distinct Station.State), sum(Data.Precipitation)",
+      "--output",
+      "csv",
+    ])?;
+    assert_eq!(
+      "count(*),count(distinct Station.State),sum(Data.Precipitation)\n16743,53,9695.71",
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_field_not_grouped() {
+    let result = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--select",
+      "Station.City, count(*)",
+      "--group-by",
+      "Station.State",
+    ]);
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn with_having() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--select",
+      "[Station.City] as city, count(*) as weeks",
+      "--where",
+      "[Data.Precipitation] > 0",
+      "--group-by",
+      "[Station.City]",
+      "--having",
+      "weeks > 45 and avg([Data.Temperature.Avg Temp]) < 40",
+      "--order-by",
+      "weeks desc",
+    ])?;
+    assert_eq!(
+      "
+city                weeks
+St Paul Island      48
+International Falls 48
+Kenai               47
+      "
+      .trim(),
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_where_comparing_numbers() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--select",
+      "[Date.Full] as date, Station.City",
+      "--where",
+      "[Data.Temperature.Avg Temp] >= 90",
+      "--limit",
+      "3",
+    ])?;
+    assert_eq!(
+      "
+date       Station.City
+2016-06-05 Phoenix
+2016-06-05 Las Vegas
+2016-06-12 Phoenix
+      "
+      .trim(),
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_distinct() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--select",
+      "Station.State as state",
+      "--distinct",
+      "--order-by",
+      "state desc",
+      "--limit",
+      "2",
+    ])?;
+    assert_eq!("state\nWyoming\nWisconsin", out);
+    Ok(())
+  }
+
+  #[test]
+  fn with_distinct_and_count() -> Result<(), Box<dyn Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    for memory_limit in &["1G", "2K"] {
+      let out = get_output_for_args(&[
+        "example_data/weather.csv",
+        "--select",
+        "Station.State, Station.City",
+        "--distinct",
+        "--count",
+        "--memory-limit",
+        memory_limit,
+        "--temp-dir",
+        temp_dir.path().to_str().unwrap(),
+      ])?;
+      assert_eq!("318 rows", out);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn with_frequency() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--frequency",
+      "[Station.State]",
+      "--top",
+      "3",
+      "--where",
+      "[Data.Temperature.Avg Temp] > 80",
+      "--output",
+      "csv",
+    ])?;
+    assert_eq!(
+      "
+Station.State,count,percentage
+Texas,319,22.39
+Florida,220,15.44
+Mississippi,101,7.09
+(other),785,55.09
+      "
+      .trim(),
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_describe() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--describe",
+      "--select",
+      "Station.State, [Data.Wind.Speed]",
+      "--where",
+      "Station.State = 'Alaska'",
+      "--output",
+      "csv",
+    ])?;
+    assert_eq!(
+      "
+column,type,non_null,null,distinct,min,max,mean,stddev,examples
+Station.State,string,1719,0,1,Alaska,Alaska,,,Alaska
+Data.Wind.Speed,float,1719,0,861,0.0,27.58,6.42995927865,4.56728349458,7.8 | 8.7 | 16.46
+      "
+      .trim(),
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_percentiles() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--select",
+      "[Station.State], median([Data.Temperature.Avg Temp]), percentile([Data.Wind.Speed], 0.95) as p95",
+      "--group-by",
+      "[Station.State]",
+      "--limit",
+      "2",
+      "--output",
+      "csv",
+    ])?;
+    assert_eq!(
+      "
+Station.State,median(Data.Temperature.Avg Temp),p95
+Alabama,68,8.2415
+Alaska,44,15.216
+      "
+      .trim(),
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_histogram() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--histogram",
+      "[Data.Temperature.Avg Temp]",
+      "--bins",
+      "3",
+      "--where",
+      "Station.State = 'Alaska'",
+      "--output",
+      "csv",
+    ])?;
+    assert_eq!("from,to,count\n-27,5,94\n5,37,489\n37,69,1136", out);
+    Ok(())
+  }
+
+  #[test]
+  fn with_pivot() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--pivot",
+      "rows=[Station.State] cols=[Date.Year],[Date.Month]",
+      "--limit",
+      "2",
+      "--output",
+      "csv",
+    ])?;
+    assert_eq!(
+      "
+Station.State,2016/1,2016/2,2016/3,2016/4,2016/5,2016/6,2016/7,2016/8,2016/9,2016/10,2016/11,2016/12,2017/1
+Alabama,20,16,16,16,20,16,20,16,16,20,16,16,4
+Alaska,155,124,129,132,165,129,160,132,129,165,129,136,34
+      "
+      .trim(),
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_pivot_and_conflicting_options() {
+    for (option, value) in &[
+      ("--select", "Station.State"),
+      ("--group-by", "Station.State"),
+      ("--having", "count(*) > 1"),
+      ("--describe", ""),
+    ] {
+      let mut args = vec![
+        "example_data/weather.csv",
+        "--pivot",
+        "rows=[Station.State] cols=[Date.Year]",
+        option,
+      ];
+      if !value.is_empty() {
+        args.push(value);
+      }
+      assert!(get_output_for_args(&args).is_err());
+    }
+  }
+
+  #[test]
+  fn with_too_many_pivot_columns() {
+    let result = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--pivot",
+      "rows=[Date.Month] cols=[Station.City]",
+      "--max-pivot-columns",
+      "20",
+    ]);
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn with_melt() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--melt",
+      "id=[Date.Full],[Station.Code] values=Data.Temperature.*",
+      "--where",
+      "[Station.Code] = 'BHM'",
+      "--select",
+      "Date.Full, key, value",
+      "--limit",
+      "4",
+    ])?;
+    assert_eq!(
+      "
+Date.Full  key                       value
+2016-01-03 Data.Temperature.Avg Temp 39
+2016-01-03 Data.Temperature.Max Temp 46
+2016-01-03 Data.Temperature.Min Temp 32
+2016-01-10 Data.Temperature.Avg Temp 44
+      "
+      .trim(),
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_join() -> Result<(), Box<dyn Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let stations = temp_dir.path().join("stations.csv");
+    std::fs::write(
+      &stations,
+      "code,Station.City,elevation\nBHM,Birmingham,644\nXXX,Nowhere,0\n",
+    )?;
+    let stations = stations.to_str().unwrap();
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--join",
+      stations,
+      "--on",
+      "[Station.Code] = code",
+      "--where",
+      "[b.Station.City] = 'Birmingham'",
+      "--select",
+      "Date.Full, a.Station.City, elevation",
+      "--limit",
+      "2",
+    ])?;
+    assert_eq!(
+      "
+Date.Full  a.Station.City elevation
+2016-01-03 Birmingham     644
+2016-01-10 Birmingham     644
+      "
+      .trim(),
+      out
+    );
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--join",
+      stations,
+      "--on",
+      "[Station.Code] = code",
+      "--join-type",
+      "right",
+      "--where",
+      "[Date.Full] = ''",
+      "--select",
+      "code, [b.Station.City]",
+    ])?;
+    assert_eq!(
+      "
+code b.Station.City
+XXX  Nowhere
+      "
+      .trim(),
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_keep_if_in_and_drop_if_in() -> Result<(), Box<dyn Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let allowed = temp_dir.path().join("allowed.csv");
+    std::fs::write(
+      &allowed,
+      "code,Station.City\nBHM,Birmingham\nANC,Anchorage\n",
+    )?;
+    let allowed = allowed.to_str().unwrap();
+    let keep = format!("[Station.Code]={}:code", allowed);
+    let out = get_output_for_args(&["example_data/weather.csv", "--keep-if-in", &keep, "--count"])?;
+    assert_eq!("106 rows", out);
+    let drop = format!("{}:Station.City", allowed);
+    let out = get_output_for_args(&["example_data/weather.csv", "--drop-if-in", &drop, "--count"])?;
+    assert_eq!("16,637 rows", out);
+    let unknown = format!("{}:elevation", allowed);
+    assert!(get_output_for_args(&["example_data/weather.csv", "--keep-if-in", &unknown]).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn with_conflicting_modes() {
+    let modes: &[&[&str]] = &[
+      &["--count"],
+      &["--describe"],
+      &["--frequency", "Station.State"],
+      &["--histogram", "[Data.Temperature.Avg Temp]"],
+    ];
+    for (i, mode) in modes.iter().enumerate() {
+      for other in &modes[i + 1..] {
+        let args = [&["example_data/weather.csv"], *mode, *other].concat();
+        assert!(get_output_for_args(&args).is_err());
+      }
+    }
+  }
+
+  #[test]
+  fn with_other_files_not_compressed_like_the_input() -> Result<(), Box<dyn Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let input = temp_dir.path().join("input.csv.gz");
+    let mut encoder = flate2::write::GzEncoder::new(
+      std::fs::File::create(&input)?,
+      flate2::Compression::default(),
+    );
+    encoder.write_all(b"id,name\n1,one\n2,two\n")?;
+    encoder.finish()?;
+    let other = temp_dir.path().join("other.csv");
+    std::fs::write(&other, "id,label\n2,deux\n")?;
+    let input = input.to_str().unwrap();
+    let other = other.to_str().unwrap();
+    let out = get_output_for_args(&[
+      input,
+      "--compression",
+      "gzip",
+      "--join",
+      other,
+      "--on",
+      "id = id",
+      "--select",
+      "name, label",
+    ])?;
+    assert_eq!("name label\ntwo  deux", out);
+    let keep = format!("{}:id", other);
+    let out = get_output_for_args(&[input, "--compression", "gzip", "--keep-if-in", &keep])?;
+    assert_eq!("id name\n2  two", out);
+    Ok(())
+  }
+
+  #[test]
+  fn with_bad_rows_in_the_joined_file() -> Result<(), Box<dyn Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let stations = temp_dir.path().join("stations.csv");
+    std::fs::write(&stations, "code,elevation\nBHM,644\nANC\n")?;
+    let args = Args::try_parse_from([
+      "csvread",
+      "example_data/weather.csv",
+      "--join",
+      stations.to_str().unwrap(),
+      "--on",
+      "[Station.Code] = code",
+      "--skip-bad-rows",
+    ])?;
+    let (dataset, all_bad_rows) = open_dataset(&args)?;
+    assert_eq!(53, dataset.records.count());
+    let counts: Vec<usize> = all_bad_rows
+      .iter()
+      .map(|bad_rows| bad_rows.borrow().count)
+      .collect();
+    assert_eq!(vec![0, 1], counts);
+    Ok(())
+  }
+
+  #[test]
+  fn with_bad_rows_in_the_keep_if_in_file() -> Result<(), Box<dyn Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let allowed = temp_dir.path().join("allowed.csv");
+    std::fs::write(&allowed, "code\nBHM\nANC,x\n")?;
+    let keep = format!("[Station.Code]={}:code", allowed.to_str().unwrap());
+    let args = Args::try_parse_from([
+      "csvread",
+      "example_data/weather.csv",
+      "--keep-if-in",
+      &keep,
+      "--skip-bad-rows",
+    ])?;
+    let (dataset, all_bad_rows) = open_dataset(&args)?;
+    assert_eq!(53, dataset.records.count());
+    let counts: Vec<usize> = all_bad_rows
+      .iter()
+      .map(|bad_rows| bad_rows.borrow().count)
+      .collect();
+    assert_eq!(vec![0, 1], counts);
+    Ok(())
+  }
+
+  #[test]
+  fn with_join_and_file_column() -> Result<(), Box<dyn Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let stations = temp_dir.path().join("stations.csv");
+    std::fs::write(&stations, "code,elevation\nBHM,644\n")?;
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--file-column",
+      "--join",
+      stations.to_str().unwrap(),
+      "--on",
+      "[Station.Code] = code",
+      "--where",
+      "_file = 'example_data/weather.csv'",
+      "--select",
+      "_file, elevation",
+      "--limit",
+      "1",
+    ])?;
+    assert_eq!(
+      "
+_file                    elevation
+example_data/weather.csv 644
+      "
+      .trim(),
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_order_by_alias() -> Result<(), Box<dyn Error>> {
+    let out = get_output_for_args(&[
+      "example_data/weather.csv",
+      "--select",
+      "Station.State as state, [Data.Temperature.Avg Temp] as temp",
+      "--order-by",
+      "temp desc, state",
+      "--limit",
+      "2",
+    ])?;
+    assert_eq!(
+      "
+state   temp
+Nevada  100
+Arizona 99
+      "
+      .trim(),
+      out
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn with_modes_and_ignored_options() {
+    let modes: &[&[&str]] = &[
+      &["--describe"],
+      &["--frequency", "Station.State"],
+      &["--histogram", "[Data.Temperature.Avg Temp]"],
+    ];
+    let options: &[&[&str]] = &[
+      &["--group-by", "Station.State"],
+      &["--order-by", "Station.State"],
+      &["--distinct"],
+    ];
+    for mode in modes {
+      for option in options {
+        let args = [&["example_data/weather.csv"], *mode, *option].concat();
+        assert!(get_output_for_args(&args).is_err());
+      }
+    }
+    for mode in &modes[1..] {
+      let args = [
+        &["example_data/weather.csv"],
+        *mode,
+        &["--select", "Station.State"],
+      ]
+      .concat();
+      assert!(get_output_for_args(&args).is_err());
+    }
+  }
 }
@@ -1,38 +1,243 @@
 use nom::branch::alt;
-use nom::bytes::complete::{tag, take_while};
-use nom::character::complete::multispace0;
-use nom::combinator::all_consuming;
+use nom::bytes::complete::{tag, tag_no_case, take_while, take_while1};
+use nom::character::complete::{multispace0, multispace1};
+use nom::combinator::{all_consuming, map, opt};
 use nom::sequence::tuple;
 use nom::IResult;
+use std::fmt::{Display, Formatter};
 
 pub type ParsingError<'a> = nom::Err<nom::error::Error<&'a str>>;
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AggregateFunction {
+  Count,
+  CountDistinct,
+  Sum,
+  Avg,
+  Min,
+  Max,
+  Median,
+  Percentile(f64),
+  ApproxPercentile(f64),
+}
+
+/// An aggregate function applied to a field, or to all the rows (`count(*)`)
+/// when `field` is `None`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Aggregate {
+  pub function: AggregateFunction,
+  pub field: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expression {
+  Column(String),
+  Aggregate(Aggregate),
+}
+
+impl Display for Expression {
+  fn fmt(&self, formatter: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+    match self {
+      Expression::Column(name) => formatter.write_str(name),
+      Expression::Aggregate(aggregate) => {
+        let (name, percentile) = match aggregate.function {
+          AggregateFunction::Count | AggregateFunction::CountDistinct => ("count", None),
+          AggregateFunction::Sum => ("sum", None),
+          AggregateFunction::Avg => ("avg", None),
+          AggregateFunction::Min => ("min", None),
+          AggregateFunction::Max => ("max", None),
+          AggregateFunction::Median => ("median", None),
+          AggregateFunction::Percentile(percentile) => ("percentile", Some(percentile)),
+          AggregateFunction::ApproxPercentile(percentile) => {
+            ("approx_percentile", Some(percentile))
+          }
+        };
+        let distinct = if aggregate.function == AggregateFunction::CountDistinct {
+          "distinct "
+        } else {
+          ""
+        };
+        let field = aggregate.field.as_deref().unwrap_or("*");
+        match percentile {
+          Some(percentile) => formatter.write_fmt(format_args!(
+            "{}({}{}, {})",
+            name, distinct, field, percentile
+          )),
+          None => formatter.write_fmt(format_args!("{}({}{})", name, distinct, field)),
+        }
+      }
+    }
+  }
+}
+
+/// A selected expression, displayed with its alias (`count(*) as total`) if any.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SelectField {
+  pub expression: Expression,
+  pub alias: Option<String>,
+}
+
+impl SelectField {
+  pub fn new(expression: Expression) -> Self {
+    SelectField {
+      expression,
+      alias: None,
+    }
+  }
+}
+
+impl Display for SelectField {
+  fn fmt(&self, formatter: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+    match &self.alias {
+      Some(alias) => formatter.write_str(alias),
+      None => self.expression.fmt(formatter),
+    }
+  }
+}
+
 #[derive(Debug, PartialEq, Clone)]
-pub struct SelectFields(pub Vec<String>);
+pub struct SelectFields(pub Vec<SelectField>);
 
 impl SelectFields {
-  pub fn parse(select: &str) -> Result<Self, ParsingError> {
-    match all_consuming(Self::parse_select)(&select) {
+  pub fn parse(select: &str) -> Result<Self, ParsingError<'_>> {
+    match all_consuming(Self::parse_select)(select) {
       Ok((_, query)) => Ok(query),
       Err(err) => Err(err),
     }
   }
 
-  fn parse_select(input: &str) -> IResult<&str, Self> {
-    return alt((Self::parse_several_fields, Self::parse_one_field))(input);
+  pub fn parse_select(input: &str) -> IResult<&str, Self> {
+    alt((Self::parse_several_fields, Self::parse_one_field))(input)
+  }
+
+  pub fn has_aggregates(&self) -> bool {
+    self
+      .0
+      .iter()
+      .any(|field| matches!(field.expression, Expression::Aggregate(_)))
   }
 
   fn parse_several_fields(input: &str) -> IResult<&str, Self> {
-    let (input, (field, _, _, mut fields)) =
-      tuple((Self::parse_field, multispace0, tag(","), Self::parse_select))(input)?;
-    let mut new_fields = vec![String::from(field)];
+    let (input, (field, _, _, mut fields)) = tuple((
+      Self::parse_select_field,
+      multispace0,
+      tag(","),
+      Self::parse_select,
+    ))(input)?;
+    let mut new_fields = vec![field];
     new_fields.append(&mut fields.0);
-    return Ok((input, SelectFields(new_fields)));
+    Ok((input, SelectFields(new_fields)))
   }
 
   fn parse_one_field(input: &str) -> IResult<&str, Self> {
-    let (input, field) = Self::parse_field(input)?;
-    return Ok((input, SelectFields(vec![String::from(field)])));
+    let (input, field) = Self::parse_select_field(input)?;
+    Ok((input, SelectFields(vec![field])))
+  }
+
+  fn parse_select_field(input: &str) -> IResult<&str, SelectField> {
+    let (input, (expression, alias)) = tuple((
+      Self::parse_expression,
+      opt(tuple((tag_no_case("as"), multispace1, Self::parse_field))),
+    ))(input)?;
+    Ok((
+      input,
+      SelectField {
+        expression,
+        alias: alias.map(|(_, _, alias)| String::from(alias)),
+      },
+    ))
+  }
+
+  fn parse_expression(input: &str) -> IResult<&str, Expression> {
+    alt((
+      map(Self::parse_aggregate, Expression::Aggregate),
+      map(Self::parse_field, |field| {
+        Expression::Column(String::from(field))
+      }),
+    ))(input)
+  }
+
+  /// Parses an aggregate like `count(*)` or `avg([Column 1])`, also used to
+  /// refer to aggregates in the having clause.
+  pub fn parse_aggregate(input: &str) -> IResult<&str, Aggregate> {
+    let (rest, (_, function, _, _, _, distinct, field, _, percentile, _, _)) = tuple((
+      multispace0,
+      alt((
+        tag_no_case("count"),
+        tag_no_case("sum"),
+        tag_no_case("avg"),
+        tag_no_case("min"),
+        tag_no_case("max"),
+        tag_no_case("median"),
+        tag_no_case("percentile"),
+        tag_no_case("approx_percentile"),
+      )),
+      multispace0,
+      tag("("),
+      multispace0,
+      opt(tuple((tag_no_case("distinct"), multispace1))),
+      alt((
+        map(tag("*"), |_| None),
+        map(Self::parse_aggregated_field, Some),
+      )),
+      multispace0,
+      opt(tuple((
+        tag(","),
+        multispace0,
+        take_while1(|c: char| c.is_ascii_digit() || c == '.'),
+        multispace0,
+      ))),
+      tag(")"),
+      multispace0,
+    ))(input)?;
+    let percentile = percentile.map(|(_, _, percentile, _)| {
+      percentile
+        .parse::<f64>()
+        .ok()
+        .filter(|p| (0.0..=1.0).contains(p))
+    });
+    let function = match (
+      function.to_ascii_lowercase().as_str(),
+      distinct.is_some(),
+      percentile,
+    ) {
+      ("count", false, None) => AggregateFunction::Count,
+      ("count", true, None) if field.is_some() => AggregateFunction::CountDistinct,
+      ("sum", false, None) if field.is_some() => AggregateFunction::Sum,
+      ("avg", false, None) if field.is_some() => AggregateFunction::Avg,
+      ("min", false, None) if field.is_some() => AggregateFunction::Min,
+      ("max", false, None) if field.is_some() => AggregateFunction::Max,
+      ("median", false, None) if field.is_some() => AggregateFunction::Median,
+      ("percentile", false, Some(Some(percentile))) if field.is_some() => {
+        AggregateFunction::Percentile(percentile)
+      }
+      ("approx_percentile", false, Some(Some(percentile))) if field.is_some() => {
+        AggregateFunction::ApproxPercentile(percentile)
+      }
+      // Not a column name either, so there is no need to try other parsers.
+      _ => {
+        return Err(nom::Err::Failure(nom::error::Error::new(
+          input,
+          nom::error::ErrorKind::Verify,
+        )))
+      }
+    };
+    Ok((
+      rest,
+      Aggregate {
+        function,
+        field: field.map(String::from),
+      },
+    ))
+  }
+
+  /// Inside the parentheses, the field can contain spaces even without
+  /// brackets, so that aggregates can be parsed back from their names.
+  fn parse_aggregated_field(input: &str) -> IResult<&str, &str> {
+    alt((
+      Self::parse_field_with_brackets,
+      map(take_while1(|c| c != ',' && c != ')'), str::trim_end),
+    ))(input)
   }
 
   fn parse_field(input: &str) -> IResult<&str, &str> {
@@ -48,7 +253,7 @@ impl SelectFields {
       take_while(|c| c != ' ' && c != ','),
       multispace0,
     ))(input)?;
-    return Ok((input, field));
+    Ok((input, field))
   }
 
   fn parse_field_with_brackets(input: &str) -> IResult<&str, &str> {
@@ -59,7 +264,7 @@ impl SelectFields {
       tag("]"),
       multispace0,
     ))(input)?;
-    return Ok((input, field));
+    Ok((input, field))
   }
 }
 
@@ -67,10 +272,21 @@ impl SelectFields {
 mod tests {
   use super::*;
 
+  fn column(name: &str) -> SelectField {
+    SelectField::new(Expression::Column(String::from(name)))
+  }
+
+  fn aggregate(function: AggregateFunction, field: Option<&str>) -> SelectField {
+    SelectField::new(Expression::Aggregate(Aggregate {
+      function,
+      field: field.map(String::from),
+    }))
+  }
+
   #[test]
   fn it_parses_a_unique_field_without_brackets() {
     assert_eq!(
-      Ok(SelectFields(vec![String::from("field1")])),
+      Ok(SelectFields(vec![column("field1")])),
       SelectFields::parse("field1")
     );
   }
@@ -78,7 +294,7 @@ mod tests {
   #[test]
   fn it_parses_a_unique_field_with_brackets() {
     assert_eq!(
-      Ok(SelectFields(vec![String::from("field 1")])),
+      Ok(SelectFields(vec![column("field 1")])),
       SelectFields::parse("[field 1]")
     );
   }
@@ -87,10 +303,10 @@ mod tests {
   fn it_parses_several_fields_without_brackets() {
     assert_eq!(
       Ok(SelectFields(vec![
-        String::from("field1"),
-        String::from("field2"),
-        String::from("field3"),
-        String::from("field4")
+        column("field1"),
+        column("field2"),
+        column("field3"),
+        column("field4")
       ])),
       SelectFields::parse("  field1, field2,field3  ,  field4  ")
     );
@@ -100,12 +316,97 @@ mod tests {
   fn it_parses_several_fields_with_brackets() {
     assert_eq!(
       Ok(SelectFields(vec![
-        String::from("field 1"),
-        String::from("field2"),
-        String::from("field 3"),
-        String::from("field4")
+        column("field 1"),
+        column("field2"),
+        column("field 3"),
+        column("field4")
       ])),
       SelectFields::parse("  [field 1], field2,[field 3]  ,  field4  ")
     );
   }
+
+  #[test]
+  fn it_parses_aggregates() {
+    assert_eq!(
+      Ok(SelectFields(vec![
+        column("field 1"),
+        aggregate(AggregateFunction::Count, None),
+        aggregate(AggregateFunction::CountDistinct, Some("field2")),
+        aggregate(AggregateFunction::Avg, Some("field 3")),
+        aggregate(AggregateFunction::Max, Some("field4")),
+      ])),
+      SelectFields::parse(
+        "[field 1], count(*), COUNT(distinct field2), avg([field 3]),max( field4 )"
+      )
+    );
+  }
+
+  #[test]
+  fn it_parses_aggregates_from_their_names() {
+    assert_eq!(
+      Ok(SelectFields(vec![aggregate(
+        AggregateFunction::CountDistinct,
+        Some("field 1")
+      )])),
+      SelectFields::parse("count(distinct field 1 )")
+    );
+  }
+
+  #[test]
+  fn it_parses_percentiles() {
+    let select = SelectFields::parse(
+      "median([field 1]), percentile(field2, 0.95), APPROX_PERCENTILE(field3,0.5)",
+    )
+    .unwrap();
+    assert_eq!(
+      vec![
+        aggregate(AggregateFunction::Median, Some("field 1")),
+        aggregate(AggregateFunction::Percentile(0.95), Some("field2")),
+        aggregate(AggregateFunction::ApproxPercentile(0.5), Some("field3")),
+      ],
+      select.0
+    );
+    assert_eq!("percentile(field2, 0.95)", select.0[1].to_string());
+    assert!(SelectFields::parse("percentile(field1)").is_err());
+    assert!(SelectFields::parse("percentile(field1, 95)").is_err());
+    assert!(SelectFields::parse("median(field1, 0.5)").is_err());
+  }
+
+  #[test]
+  fn it_rejects_invalid_aggregates() {
+    assert!(SelectFields::parse("sum(*)").is_err());
+    assert!(SelectFields::parse("avg(distinct field1)").is_err());
+  }
+
+  #[test]
+  fn it_displays_fields() {
+    let select = SelectFields::parse("[field 1], count(*), count(distinct [field 2])").unwrap();
+    assert_eq!(
+      vec!["field 1", "count(*)", "count(distinct field 2)"],
+      select.0.iter().map(ToString::to_string).collect::<Vec<_>>()
+    );
+  }
+
+  #[test]
+  fn it_parses_aliases() {
+    let select = SelectFields::parse("[field 1] as f1, count(*) AS [row count], field2").unwrap();
+    assert_eq!(
+      vec![
+        SelectField {
+          alias: Some(String::from("f1")),
+          ..column("field 1")
+        },
+        SelectField {
+          alias: Some(String::from("row count")),
+          ..aggregate(AggregateFunction::Count, None)
+        },
+        column("field2"),
+      ],
+      select.0
+    );
+    assert_eq!(
+      vec!["f1", "row count", "field2"],
+      select.0.iter().map(ToString::to_string).collect::<Vec<_>>()
+    );
+  }
 }
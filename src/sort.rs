@@ -0,0 +1,522 @@
+use crate::errors::AppError;
+use crate::order_parser::{OrderBy, SortKey};
+use crate::types::parse_float;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+pub type SortedRows = Box<dyn Iterator<Item = Result<Vec<String>, Box<dyn Error>>>>;
+
+// Approximate memory used by a `String` or a `Vec` besides its content.
+pub const ALLOCATION_OVERHEAD: usize = 32;
+// Maximum number of runs merged at once, which keeps the number of open files
+// low.
+const MERGE_WIDTH: usize = 64;
+
+#[derive(Debug, PartialEq, Clone)]
+enum SortValue {
+  Null,
+  Number(f64),
+  Text(String),
+}
+
+impl SortValue {
+  fn serialize(&self) -> String {
+    match self {
+      SortValue::Null => String::new(),
+      SortValue::Number(number) => number.to_string(),
+      SortValue::Text(text) => text.clone(),
+    }
+  }
+
+  fn of(value: &str) -> Self {
+    if value.is_empty() {
+      SortValue::Null
+    } else {
+      match parse_float(value) {
+        Some(number) => SortValue::Number(number),
+        None => SortValue::Text(String::from(value)),
+      }
+    }
+  }
+
+  /// Numbers are compared numerically, and come before texts.
+  fn compare(&self, other: &Self, key: &SortKey) -> Ordering {
+    let ordering = match (self, other) {
+      (SortValue::Null, SortValue::Null) => return Ordering::Equal,
+      (SortValue::Null, _) if key.nulls_first => return Ordering::Less,
+      (SortValue::Null, _) => return Ordering::Greater,
+      (_, SortValue::Null) if key.nulls_first => return Ordering::Greater,
+      (_, SortValue::Null) => return Ordering::Less,
+      (SortValue::Number(a), SortValue::Number(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+      (SortValue::Number(_), SortValue::Text(_)) => Ordering::Less,
+      (SortValue::Text(_), SortValue::Number(_)) => Ordering::Greater,
+      (SortValue::Text(a), SortValue::Text(b)) => a.cmp(b),
+    };
+    if key.descending {
+      ordering.reverse()
+    } else {
+      ordering
+    }
+  }
+}
+
+struct SortRow {
+  values: Vec<SortValue>,
+  row: Vec<String>,
+}
+
+impl SortRow {
+  fn memory_size(&self) -> usize {
+    let values: usize = self
+      .values
+      .iter()
+      .map(|value| match value {
+        SortValue::Text(text) => text.len() + ALLOCATION_OVERHEAD,
+        _ => ALLOCATION_OVERHEAD,
+      })
+      .sum();
+    let row: usize = self
+      .row
+      .iter()
+      .map(|cell| cell.len() + ALLOCATION_OVERHEAD)
+      .sum();
+    values + row + 2 * ALLOCATION_OVERHEAD
+  }
+}
+
+/// Sorts the rows on the given keys. The sort is stable, so rows with equal
+/// keys keep the order in which they were read. When only the first rows are
+/// needed, only those are kept in memory.
+///
+/// Otherwise, once the rows exceed the memory limit, they are sorted and
+/// written to a temporary run file, and the runs are merged at the end. The run
+/// files are deleted by the OS as soon as they are closed, including when the
+/// program is interrupted.
+///
+/// At most `merge_width` runs are merged at once: as soon as that many runs of
+/// the same level exist, they are merged into a single run of the next level,
+/// and the remaining runs are merged in several passes if needed.
+pub struct Sorter {
+  keys: Rc<Vec<SortKey>>,
+  positions: Vec<usize>,
+  top: Option<usize>,
+  rows: Vec<SortRow>,
+  memory: usize,
+  memory_limit: Option<usize>,
+  temp_dir: PathBuf,
+  runs: Vec<(usize, File)>,
+  merge_width: usize,
+}
+
+impl Sorter {
+  pub fn new(
+    order_by: OrderBy,
+    header_positions: &HashMap<String, usize>,
+    top: Option<usize>,
+  ) -> Result<Self, AppError> {
+    let mut positions = Vec::with_capacity(order_by.0.len());
+    for key in &order_by.0 {
+      match header_positions.get(&key.field) {
+        Some(&position) => positions.push(position),
+        None => return Err(AppError::InvalidFieldInOrderByClause(key.field.clone())),
+      }
+    }
+    Ok(Sorter {
+      keys: Rc::new(order_by.0),
+      positions,
+      top,
+      rows: Vec::new(),
+      memory: 0,
+      memory_limit: None,
+      temp_dir: std::env::temp_dir(),
+      runs: Vec::new(),
+      merge_width: MERGE_WIDTH,
+    })
+  }
+
+  pub fn with_memory_limit(mut self, memory_limit: usize, temp_dir: Option<PathBuf>) -> Self {
+    self.memory_limit = Some(memory_limit);
+    if let Some(temp_dir) = temp_dir {
+      self.temp_dir = temp_dir;
+    }
+    self
+  }
+
+  pub fn push(
+    &mut self,
+    record: &csv::StringRecord,
+    row: Vec<String>,
+  ) -> Result<(), Box<dyn Error>> {
+    let values = self
+      .positions
+      .iter()
+      .map(|&position| SortValue::of(record.get(position).unwrap_or("")))
+      .collect();
+    let sort_row = SortRow { values, row };
+    self.memory += sort_row.memory_size();
+    self.rows.push(sort_row);
+    match (self.top, self.memory_limit) {
+      (Some(top), _) if self.rows.len() >= std::cmp::max(2 * top, 1024) => {
+        self.sort();
+        self.rows.truncate(top);
+      }
+      (None, Some(memory_limit)) if self.memory > memory_limit => self.spill()?,
+      _ => (),
+    }
+    Ok(())
+  }
+
+  pub fn finish(mut self) -> Result<SortedRows, Box<dyn Error>> {
+    self.sort();
+    if let Some(top) = self.top {
+      self.rows.truncate(top);
+    }
+    let rows = std::mem::take(&mut self.rows);
+    if self.runs.is_empty() {
+      return Ok(Box::new(rows.into_iter().map(|sort_row| Ok(sort_row.row))));
+    }
+    let mut files: Vec<File> = self.runs.drain(..).map(|(_, file)| file).collect();
+    while files.len() >= self.merge_width {
+      let mut merged = Vec::with_capacity(files.len() / self.merge_width + 1);
+      let mut files_iter = files.into_iter().peekable();
+      while files_iter.peek().is_some() {
+        let chunk: Vec<File> = files_iter.by_ref().take(self.merge_width).collect();
+        merged.push(self.merge_files(chunk)?);
+      }
+      files = merged;
+    }
+    let mut runs: Vec<Run> = Vec::with_capacity(files.len() + 1);
+    for file in files {
+      runs.push(Box::new(read_run(file, self.keys.len())));
+    }
+    runs.push(Box::new(rows.into_iter().map(Ok)));
+    let merge = Merge::new(self.keys.clone(), runs)?;
+    Ok(Box::new(
+      merge.map(|sort_row| sort_row.map(|sort_row| sort_row.row)),
+    ))
+  }
+
+  fn sort(&mut self) {
+    let keys = &self.keys;
+    self
+      .rows
+      .sort_by(|a, b| compare(keys, &a.values, &b.values));
+  }
+
+  fn spill(&mut self) -> Result<(), Box<dyn Error>> {
+    self.sort();
+    let rows = std::mem::take(&mut self.rows);
+    let file = write_run(&self.temp_dir, rows.into_iter().map(Ok))?;
+    self.runs.push((0, file));
+    self.memory = 0;
+    // The runs are always merged with their neighbours, so that rows with equal
+    // keys keep their order.
+    while let Some(&(level, _)) = self.runs.last() {
+      let same_level = self
+        .runs
+        .iter()
+        .rev()
+        .take_while(|(run_level, _)| *run_level == level)
+        .count();
+      if same_level < self.merge_width {
+        break;
+      }
+      let files = self
+        .runs
+        .split_off(self.runs.len() - same_level)
+        .into_iter()
+        .map(|(_, file)| file)
+        .collect();
+      let file = self.merge_files(files)?;
+      self.runs.push((level + 1, file));
+    }
+    Ok(())
+  }
+
+  fn merge_files(&self, files: Vec<File>) -> Result<File, Box<dyn Error>> {
+    let runs: Vec<Run> = files
+      .into_iter()
+      .map(|file| Box::new(read_run(file, self.keys.len())) as Run)
+      .collect();
+    write_run(&self.temp_dir, Merge::new(self.keys.clone(), runs)?)
+  }
+}
+
+type Run = Box<dyn Iterator<Item = Result<SortRow, Box<dyn Error>>>>;
+
+fn write_run(
+  temp_dir: &Path,
+  rows: impl Iterator<Item = Result<SortRow, Box<dyn Error>>>,
+) -> Result<File, Box<dyn Error>> {
+  let mut file = tempfile::tempfile_in(temp_dir)?;
+  {
+    let mut writer = csv::Writer::from_writer(BufWriter::new(&mut file));
+    for sort_row in rows {
+      let sort_row = sort_row?;
+      let mut record: Vec<String> = sort_row.values.iter().map(SortValue::serialize).collect();
+      record.extend(sort_row.row);
+      writer.write_record(&record)?;
+    }
+    writer.flush()?;
+  }
+  file.seek(SeekFrom::Start(0))?;
+  Ok(file)
+}
+
+fn read_run(file: File, keys: usize) -> impl Iterator<Item = Result<SortRow, Box<dyn Error>>> {
+  csv::ReaderBuilder::new()
+    .has_headers(false)
+    .flexible(true)
+    .from_reader(BufReader::new(file))
+    .into_records()
+    .map(move |record| {
+      let record = record?;
+      Ok(SortRow {
+        values: record.iter().take(keys).map(SortValue::of).collect(),
+        row: record.iter().skip(keys).map(String::from).collect(),
+      })
+    })
+}
+
+/// The next row of a run, ordered so that the greatest head in the heap is the
+/// smallest row, and on equal keys the one from the earliest run.
+struct Head {
+  keys: Rc<Vec<SortKey>>,
+  sort_row: SortRow,
+  run: usize,
+}
+
+impl Ord for Head {
+  fn cmp(&self, other: &Self) -> Ordering {
+    compare(&self.keys, &other.sort_row.values, &self.sort_row.values)
+      .then_with(|| other.run.cmp(&self.run))
+  }
+}
+
+impl PartialOrd for Head {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl PartialEq for Head {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for Head {}
+
+/// Merges sorted runs. On equal keys, the row from the earliest run comes
+/// first, which keeps the sort stable.
+struct Merge {
+  keys: Rc<Vec<SortKey>>,
+  runs: Vec<Run>,
+  heads: BinaryHeap<Head>,
+}
+
+impl Merge {
+  fn new(keys: Rc<Vec<SortKey>>, mut runs: Vec<Run>) -> Result<Self, Box<dyn Error>> {
+    let mut heads = BinaryHeap::with_capacity(runs.len());
+    for (run, rows) in runs.iter_mut().enumerate() {
+      if let Some(sort_row) = rows.next().transpose()? {
+        heads.push(Head {
+          keys: keys.clone(),
+          sort_row,
+          run,
+        });
+      }
+    }
+    Ok(Merge { keys, runs, heads })
+  }
+}
+
+impl Iterator for Merge {
+  type Item = Result<SortRow, Box<dyn Error>>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let head = self.heads.pop()?;
+    match self.runs[head.run].next().transpose() {
+      Ok(Some(sort_row)) => self.heads.push(Head {
+        keys: self.keys.clone(),
+        sort_row,
+        run: head.run,
+      }),
+      Ok(None) => (),
+      Err(err) => return Some(Err(err)),
+    }
+    Some(Ok(head.sort_row))
+  }
+}
+
+/// Compares two values the way they are sorted in ascending order.
+pub fn compare_values(a: &str, b: &str) -> Ordering {
+  let key = SortKey {
+    field: String::new(),
+    descending: false,
+    nulls_first: false,
+  };
+  SortValue::of(a).compare(&SortValue::of(b), &key)
+}
+
+fn compare(keys: &[SortKey], a: &[SortValue], b: &[SortValue]) -> Ordering {
+  for (key, (a, b)) in keys.iter().zip(a.iter().zip(b)) {
+    let ordering = a.compare(b, key);
+    if ordering != Ordering::Equal {
+      return ordering;
+    }
+  }
+  Ordering::Equal
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sort_with_sorter(mut sorter: Sorter, rows: &[&[&str]]) -> Vec<Vec<String>> {
+    for row in rows {
+      let record = csv::StringRecord::from(row.to_vec());
+      sorter
+        .push(
+          &record,
+          row.iter().map(|cell| String::from(*cell)).collect(),
+        )
+        .unwrap();
+    }
+    sorter
+      .finish()
+      .unwrap()
+      .collect::<Result<Vec<_>, _>>()
+      .unwrap()
+  }
+
+  fn sorter(order_by: &str, top: Option<usize>) -> Sorter {
+    let mut header_positions = HashMap::new();
+    header_positions.insert(String::from("a"), 0);
+    header_positions.insert(String::from("b"), 1);
+    Sorter::new(OrderBy::parse(order_by).unwrap(), &header_positions, top).unwrap()
+  }
+
+  fn sort(order_by: &str, top: Option<usize>, rows: &[&[&str]]) -> Vec<Vec<String>> {
+    sort_with_sorter(sorter(order_by, top), rows)
+  }
+
+  #[test]
+  fn it_sorts_numbers_numerically() {
+    assert_eq!(
+      vec![vec!["-2.5"], vec!["3"], vec!["12"], vec!["abc"]],
+      sort("a", None, &[&["12"], &["abc"], &["-2.5"], &["3"]])
+    );
+  }
+
+  #[test]
+  fn it_places_nulls() {
+    let rows: &[&[&str]] = &[&["2"], &[""], &["1"]];
+    assert_eq!(vec![vec!["1"], vec!["2"], vec![""]], sort("a", None, rows));
+    assert_eq!(
+      vec![vec![""], vec!["2"], vec!["1"]],
+      sort("a desc", None, rows)
+    );
+    assert_eq!(
+      vec![vec![""], vec!["1"], vec!["2"]],
+      sort("a nulls first", None, rows)
+    );
+  }
+
+  #[test]
+  fn it_sorts_on_several_keys_and_is_stable() {
+    assert_eq!(
+      vec![
+        vec!["y", "2", "first"],
+        vec!["y", "2", "second"],
+        vec!["x", "1", "third"],
+        vec!["x", "3", "fourth"]
+      ],
+      sort(
+        "a desc, b",
+        None,
+        &[
+          &["x", "3", "fourth"],
+          &["y", "2", "first"],
+          &["x", "1", "third"],
+          &["y", "2", "second"]
+        ]
+      )
+    );
+  }
+
+  #[test]
+  fn it_keeps_only_the_top_rows() {
+    let rows: Vec<Vec<String>> = (0..5000).rev().map(|i| vec![i.to_string()]).collect();
+    let rows: Vec<Vec<&str>> = rows
+      .iter()
+      .map(|row| row.iter().map(String::as_str).collect())
+      .collect();
+    let rows: Vec<&[&str]> = rows.iter().map(Vec::as_slice).collect();
+    assert_eq!(
+      vec![vec!["0"], vec!["1"], vec!["2"]],
+      sort("a", Some(3), &rows)
+    );
+  }
+
+  #[test]
+  fn it_merges_runs_spilled_to_disk() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let sorter = sorter("a desc, b", None).with_memory_limit(300, Some(temp_dir.path().into()));
+    let rows: &[&[&str]] = &[
+      &["1", "b", "first"],
+      &["3", "", "second"],
+      &["2", "a", "third"],
+      &["x", "a", "fourth"],
+      &["1", "b", "fifth"],
+      &["", "c", "sixth"],
+      &["3", "a", "seventh"],
+      &["1.5", "z", "eighth, with a comma"],
+    ];
+    assert_eq!(
+      vec![
+        vec!["", "c", "sixth"],
+        vec!["x", "a", "fourth"],
+        vec!["3", "a", "seventh"],
+        vec!["3", "", "second"],
+        vec!["2", "a", "third"],
+        vec!["1.5", "z", "eighth, with a comma"],
+        vec!["1", "b", "first"],
+        vec!["1", "b", "fifth"],
+      ],
+      sort_with_sorter(sorter, rows)
+    );
+    assert_eq!(0, std::fs::read_dir(temp_dir.path()).unwrap().count());
+  }
+
+  #[test]
+  fn it_merges_more_runs_than_the_merge_width() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut sorter = sorter("a", None).with_memory_limit(1, Some(temp_dir.path().into()));
+    sorter.merge_width = 3;
+    let rows: Vec<Vec<String>> = (0..100)
+      .map(|i| vec![(i % 7).to_string(), i.to_string()])
+      .collect();
+    for row in &rows {
+      sorter
+        .push(&csv::StringRecord::from(row.clone()), row.clone())
+        .unwrap();
+      assert!(sorter.runs.len() <= 12);
+    }
+    let mut expected = rows.clone();
+    expected.sort_by_key(|row| row[0].parse::<usize>().unwrap());
+    assert_eq!(
+      expected,
+      sorter
+        .finish()
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap()
+    );
+  }
+}
@@ -0,0 +1,109 @@
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ColumnType {
+  Null,
+  Boolean,
+  Integer,
+  Float,
+  String,
+}
+
+impl ColumnType {
+  pub fn of(value: &str) -> Self {
+    if value.is_empty() {
+      ColumnType::Null
+    } else if parse_boolean(value).is_some() {
+      ColumnType::Boolean
+    } else if has_leading_zero(value) {
+      ColumnType::String
+    } else if value.parse::<i64>().is_ok() {
+      ColumnType::Integer
+    } else if parse_float(value).is_some() {
+      ColumnType::Float
+    } else {
+      ColumnType::String
+    }
+  }
+
+  pub fn infer<'a>(values: impl Iterator<Item = &'a str>) -> Self {
+    values.fold(ColumnType::Null, |column_type, value| {
+      column_type.merge(ColumnType::of(value))
+    })
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      ColumnType::Null => "null",
+      ColumnType::Boolean => "boolean",
+      ColumnType::Integer => "integer",
+      ColumnType::Float => "float",
+      ColumnType::String => "string",
+    }
+  }
+
+  pub fn merge(self, other: Self) -> Self {
+    match (self, other) {
+      (ColumnType::Null, other) => other,
+      (column_type, ColumnType::Null) => column_type,
+      (ColumnType::Integer, ColumnType::Float) | (ColumnType::Float, ColumnType::Integer) => {
+        ColumnType::Float
+      }
+      (column_type, other) if column_type == other => column_type,
+      _ => ColumnType::String,
+    }
+  }
+}
+
+pub fn parse_boolean(value: &str) -> Option<bool> {
+  match value {
+    "true" | "True" | "TRUE" => Some(true),
+    "false" | "False" | "FALSE" => Some(false),
+    _ => None,
+  }
+}
+
+pub fn parse_float(value: &str) -> Option<f64> {
+  match value.parse::<f64>() {
+    Ok(float) if float.is_finite() && value.contains(|c: char| c.is_ascii_digit()) => Some(float),
+    _ => None,
+  }
+}
+
+/// Values like zip codes (`01234`) look like numbers but would lose their
+/// leading zero once converted.
+fn has_leading_zero(value: &str) -> bool {
+  let digits = value.trim_start_matches('-');
+  digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0.")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_infers_the_type_of_values() {
+    assert_eq!(ColumnType::Null, ColumnType::of(""));
+    assert_eq!(ColumnType::Boolean, ColumnType::of("true"));
+    assert_eq!(ColumnType::Integer, ColumnType::of("-21"));
+    assert_eq!(ColumnType::Float, ColumnType::of("2.57"));
+    assert_eq!(ColumnType::Float, ColumnType::of("0.0"));
+    assert_eq!(ColumnType::String, ColumnType::of("01234"));
+    assert_eq!(ColumnType::String, ColumnType::of("NaN"));
+    assert_eq!(ColumnType::String, ColumnType::of("Fairbanks, AK"));
+  }
+
+  #[test]
+  fn it_infers_the_type_of_columns() {
+    assert_eq!(
+      ColumnType::Float,
+      ColumnType::infer(vec!["1", "", "2.5"].into_iter())
+    );
+    assert_eq!(
+      ColumnType::String,
+      ColumnType::infer(vec!["1", "true"].into_iter())
+    );
+    assert_eq!(
+      ColumnType::Null,
+      ColumnType::infer(vec!["", ""].into_iter())
+    );
+  }
+}